@@ -0,0 +1,66 @@
+//! Reads a 1 MiB request through a connection, next to a stream that hands it over 8 bytes
+//! per read as the baseline the buffered reads are measured against.
+
+#![feature(test)]
+
+extern crate test;
+
+use std::io;
+
+use http::Connection;
+
+const SIZE: usize = 1024 * 1024;
+
+/// Serves `input` as a connection, at most `step` bytes per read, and discards what is written.
+struct Replayed {
+    input: io::Cursor<Vec<u8>>,
+    step: usize,
+}
+
+impl io::Read for Replayed {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let length = buf.len().min(self.step);
+
+        self.input.read(&mut buf[..length])
+    }
+}
+
+impl io::Write for Replayed {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn request() -> Vec<u8> {
+    let head = format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n", SIZE);
+
+    [head.into_bytes(), vec![b'x'; SIZE]].concat()
+}
+
+fn read(bencher: &mut test::Bencher, step: usize) {
+    let request = request();
+
+    bencher.bytes = request.len() as u64;
+
+    bencher.iter(|| {
+        let input = io::Cursor::new(request.clone());
+
+        let mut connection = Connection::new(Replayed { input, step });
+
+        test::black_box(connection.read_request().unwrap());
+    });
+}
+
+#[bench]
+fn one_mib_request(bencher: &mut test::Bencher) {
+    read(bencher, usize::MAX);
+}
+
+#[bench]
+fn one_mib_request_eight_bytes_per_read(bencher: &mut test::Bencher) {
+    read(bencher, 8);
+}
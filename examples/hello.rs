@@ -0,0 +1,22 @@
+use http::{Header, Message, MessageBuilder, Server};
+
+fn hello(_request: &Message) -> Message {
+    let body = "
+        <html>
+            <p>Hello, world!</p>
+        </html>
+    ";
+
+    MessageBuilder::new()
+        .header(Header { name: "Content-Type".into(), value: "text/html".into() })
+        .body(body)
+        .build()
+}
+
+fn main() -> Result<(), http::Error> {
+    let mut server = Server::bind(8080)?;
+
+    server.run(hello);
+
+    Ok(())
+}
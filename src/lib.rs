@@ -1,66 +1,459 @@
 #![feature(try_blocks)]
 #![feature(let_else)]
 
+use std::borrow;
+use std::cell;
 use std::error;
 use std::fmt;
+use std::fs;
 use std::io;
 use std::mem;
 use std::net;
+use std::panic;
+use std::path;
 use std::result;
+use std::slice;
+use std::str;
+use std::sync;
+use std::sync::atomic;
+use std::sync::mpsc;
+use std::thread;
+use std::time;
+use std::vec;
 
 const LOCAL_HOST: &'static str = "127.0.0.1";
+const DEFAULT_WORKERS: usize = 4;
+const DEFAULT_MAX_HEADER_BYTES: usize = 8 * 1024;
+const DEFAULT_MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+const READ_BUFFER_BYTES: usize = 8 * 1024;
+const MAX_POOLED_BUFFERS: usize = 64;
+const MAX_POOLED_BYTES: usize = 64 * 1024;
+#[cfg(feature = "gzip")]
+const MIN_COMPRESS_BYTES: usize = 1024;
+const DEFAULT_TIMEOUT: time::Duration = time::Duration::from_secs(30);
+const DEFAULT_CONTINUE_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+const DEFAULT_SERVER_NAME: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
+const ACCEPT_POLL_INTERVAL: time::Duration = time::Duration::from_millis(10);
 
 type Target = String;
 type Status = String;
-type Body<'a> = &'a str;
+
+macro_rules! headers { 
+    ($builder: ident, $($name: literal: $value: expr),*) => {
+        $($builder = $builder.header(Header { name: format!("{}", { $name }), value: format!("{}", { $value }) });)*
+    };
+}
+
+macro_rules! body { 
+    ($builder: ident, $body: expr) => {
+        $builder = $builder.body($body)
+    };
+}
 
 #[derive(Debug)]
 pub enum Error {
-    AddrInUse,
-    Malformed,
+    AddrInUse(net::SocketAddr, io::Error),
+    Malformed(MalformedKind),
+    UnsupportedVersion,
+    Io(io::Error),
+    #[cfg(feature = "tls")]
+    Tls(String),
 }
 
 type Result<T> = result::Result<T, Error>;
+type Parsed<T> = result::Result<T, MalformedKind>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MalformedKind {
+    Incomplete,
+    StartLine,
+    Method,
+    Target,
+    Version,
+    Status,
+    Header,
+    Value,
+    Host,
+    ContentLength,
+    Framing,
+    Chunk,
+    HeaderTooLarge,
+    BodyTooLarge,
+    Expectation,
+    UnexpectedBody,
+}
+
+impl fmt::Display for MalformedKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        use MalformedKind::*;
+
+        let reason = match self {
+            Incomplete => "message ends early",
+            StartLine => "invalid start line",
+            Method => "invalid method",
+            Target => "invalid request target",
+            Version => "invalid or unsupported version",
+            Status => "invalid status code",
+            Header => "invalid header field",
+            Value => "invalid header value",
+            Host => "missing or repeated Host header",
+            ContentLength => "invalid or conflicting Content-Length",
+            Framing => "both Transfer-Encoding and Content-Length",
+            Chunk => "invalid chunked body",
+            HeaderTooLarge => "header section too large",
+            BodyTooLarge => "body too large",
+            Expectation => "unsupported expectation",
+            UnexpectedBody => "body sent with a method that takes none",
+        };
+
+        f.write_str(reason)
+    }
+}
 
 #[derive(Clone, Copy, Debug)]
 #[repr(u16)]
-enum Code {
+pub enum Code {
+    Continue = 100,
+    SwitchingProtocols = 101,
+    Processing = 102,
+    EarlyHints = 103,
     Success = 200,
+    Created = 201,
+    Accepted = 202,
+    NonAuthoritativeInformation = 203,
+    NoContent = 204,
+    ResetContent = 205,
+    PartialContent = 206,
+    MultiStatus = 207,
+    AlreadyReported = 208,
+    ImUsed = 226,
+    MultipleChoices = 300,
+    MovedPermanently = 301,
+    Found = 302,
+    SeeOther = 303,
+    NotModified = 304,
+    UseProxy = 305,
+    TemporaryRedirect = 307,
+    PermanentRedirect = 308,
+    BadRequest = 400,
+    Unauthorized = 401,
+    PaymentRequired = 402,
+    Forbidden = 403,
+    NotFound = 404,
+    MethodNotAllowed = 405,
+    NotAcceptable = 406,
+    ProxyAuthenticationRequired = 407,
+    RequestTimeout = 408,
+    Conflict = 409,
+    Gone = 410,
+    LengthRequired = 411,
+    PreconditionFailed = 412,
+    PayloadTooLarge = 413,
+    UriTooLong = 414,
+    UnsupportedMediaType = 415,
+    RangeNotSatisfiable = 416,
+    ExpectationFailed = 417,
+    ImATeapot = 418,
+    MisdirectedRequest = 421,
+    UnprocessableEntity = 422,
+    Locked = 423,
+    FailedDependency = 424,
+    TooEarly = 425,
+    UpgradeRequired = 426,
+    PreconditionRequired = 428,
+    TooManyRequests = 429,
+    RequestHeaderFieldsTooLarge = 431,
+    UnavailableForLegalReasons = 451,
+    InternalServerError = 500,
+    NotImplemented = 501,
+    BadGateway = 502,
+    ServiceUnavailable = 503,
+    GatewayTimeout = 504,
+    HttpVersionNotSupported = 505,
+    VariantAlsoNegotiates = 506,
+    InsufficientStorage = 507,
+    LoopDetected = 508,
+    NotExtended = 510,
+    NetworkAuthenticationRequired = 511,
 }
 
 impl From<Code> for u16 {
     fn from(code: Code) -> Self {
-        unsafe { mem::transmute(code) }
+        use Code::*;
+
+        match code {
+            Continue => 100,
+            SwitchingProtocols => 101,
+            Processing => 102,
+            EarlyHints => 103,
+            Success => 200,
+            Created => 201,
+            Accepted => 202,
+            NonAuthoritativeInformation => 203,
+            NoContent => 204,
+            ResetContent => 205,
+            PartialContent => 206,
+            MultiStatus => 207,
+            AlreadyReported => 208,
+            ImUsed => 226,
+            MultipleChoices => 300,
+            MovedPermanently => 301,
+            Found => 302,
+            SeeOther => 303,
+            NotModified => 304,
+            UseProxy => 305,
+            TemporaryRedirect => 307,
+            PermanentRedirect => 308,
+            BadRequest => 400,
+            Unauthorized => 401,
+            PaymentRequired => 402,
+            Forbidden => 403,
+            NotFound => 404,
+            MethodNotAllowed => 405,
+            NotAcceptable => 406,
+            ProxyAuthenticationRequired => 407,
+            RequestTimeout => 408,
+            Conflict => 409,
+            Gone => 410,
+            LengthRequired => 411,
+            PreconditionFailed => 412,
+            PayloadTooLarge => 413,
+            UriTooLong => 414,
+            UnsupportedMediaType => 415,
+            RangeNotSatisfiable => 416,
+            ExpectationFailed => 417,
+            ImATeapot => 418,
+            MisdirectedRequest => 421,
+            UnprocessableEntity => 422,
+            Locked => 423,
+            FailedDependency => 424,
+            TooEarly => 425,
+            UpgradeRequired => 426,
+            PreconditionRequired => 428,
+            TooManyRequests => 429,
+            RequestHeaderFieldsTooLarge => 431,
+            UnavailableForLegalReasons => 451,
+            InternalServerError => 500,
+            NotImplemented => 501,
+            BadGateway => 502,
+            ServiceUnavailable => 503,
+            GatewayTimeout => 504,
+            HttpVersionNotSupported => 505,
+            VariantAlsoNegotiates => 506,
+            InsufficientStorage => 507,
+            LoopDetected => 508,
+            NotExtended => 510,
+            NetworkAuthenticationRequired => 511,
+        }
+    }
+}
+
+impl TryFrom<u16> for Code {
+    type Error = Error;
+
+    fn try_from(code: u16) -> Result<Self> {
+        Ok(match code {
+            100 => Self::Continue,
+            101 => Self::SwitchingProtocols,
+            102 => Self::Processing,
+            103 => Self::EarlyHints,
+            200 => Self::Success,
+            201 => Self::Created,
+            202 => Self::Accepted,
+            203 => Self::NonAuthoritativeInformation,
+            204 => Self::NoContent,
+            205 => Self::ResetContent,
+            206 => Self::PartialContent,
+            207 => Self::MultiStatus,
+            208 => Self::AlreadyReported,
+            226 => Self::ImUsed,
+            300 => Self::MultipleChoices,
+            301 => Self::MovedPermanently,
+            302 => Self::Found,
+            303 => Self::SeeOther,
+            304 => Self::NotModified,
+            305 => Self::UseProxy,
+            307 => Self::TemporaryRedirect,
+            308 => Self::PermanentRedirect,
+            400 => Self::BadRequest,
+            401 => Self::Unauthorized,
+            402 => Self::PaymentRequired,
+            403 => Self::Forbidden,
+            404 => Self::NotFound,
+            405 => Self::MethodNotAllowed,
+            406 => Self::NotAcceptable,
+            407 => Self::ProxyAuthenticationRequired,
+            408 => Self::RequestTimeout,
+            409 => Self::Conflict,
+            410 => Self::Gone,
+            411 => Self::LengthRequired,
+            412 => Self::PreconditionFailed,
+            413 => Self::PayloadTooLarge,
+            414 => Self::UriTooLong,
+            415 => Self::UnsupportedMediaType,
+            416 => Self::RangeNotSatisfiable,
+            417 => Self::ExpectationFailed,
+            418 => Self::ImATeapot,
+            421 => Self::MisdirectedRequest,
+            422 => Self::UnprocessableEntity,
+            423 => Self::Locked,
+            424 => Self::FailedDependency,
+            425 => Self::TooEarly,
+            426 => Self::UpgradeRequired,
+            428 => Self::PreconditionRequired,
+            429 => Self::TooManyRequests,
+            431 => Self::RequestHeaderFieldsTooLarge,
+            451 => Self::UnavailableForLegalReasons,
+            500 => Self::InternalServerError,
+            501 => Self::NotImplemented,
+            502 => Self::BadGateway,
+            503 => Self::ServiceUnavailable,
+            504 => Self::GatewayTimeout,
+            505 => Self::HttpVersionNotSupported,
+            506 => Self::VariantAlsoNegotiates,
+            507 => Self::InsufficientStorage,
+            508 => Self::LoopDetected,
+            510 => Self::NotExtended,
+            511 => Self::NetworkAuthenticationRequired,
+            _ => Err(Error::Malformed(MalformedKind::Status))?
+        })
+    }
+}
+
+impl Code {
+    pub fn canonical_reason(&self) -> &'static str {
+        use Code::*;
+
+        match self {
+            Continue => "Continue",
+            SwitchingProtocols => "Switching Protocols",
+            Processing => "Processing",
+            EarlyHints => "Early Hints",
+            Success => "OK",
+            Created => "Created",
+            Accepted => "Accepted",
+            NonAuthoritativeInformation => "Non-Authoritative Information",
+            NoContent => "No Content",
+            ResetContent => "Reset Content",
+            PartialContent => "Partial Content",
+            MultiStatus => "Multi-Status",
+            AlreadyReported => "Already Reported",
+            ImUsed => "IM Used",
+            MultipleChoices => "Multiple Choices",
+            MovedPermanently => "Moved Permanently",
+            Found => "Found",
+            SeeOther => "See Other",
+            NotModified => "Not Modified",
+            UseProxy => "Use Proxy",
+            TemporaryRedirect => "Temporary Redirect",
+            PermanentRedirect => "Permanent Redirect",
+            BadRequest => "Bad Request",
+            Unauthorized => "Unauthorized",
+            PaymentRequired => "Payment Required",
+            Forbidden => "Forbidden",
+            NotFound => "Not Found",
+            MethodNotAllowed => "Method Not Allowed",
+            NotAcceptable => "Not Acceptable",
+            ProxyAuthenticationRequired => "Proxy Authentication Required",
+            RequestTimeout => "Request Timeout",
+            Conflict => "Conflict",
+            Gone => "Gone",
+            LengthRequired => "Length Required",
+            PreconditionFailed => "Precondition Failed",
+            PayloadTooLarge => "Payload Too Large",
+            UriTooLong => "URI Too Long",
+            UnsupportedMediaType => "Unsupported Media Type",
+            RangeNotSatisfiable => "Range Not Satisfiable",
+            ExpectationFailed => "Expectation Failed",
+            ImATeapot => "I'm a teapot",
+            MisdirectedRequest => "Misdirected Request",
+            UnprocessableEntity => "Unprocessable Entity",
+            Locked => "Locked",
+            FailedDependency => "Failed Dependency",
+            TooEarly => "Too Early",
+            UpgradeRequired => "Upgrade Required",
+            PreconditionRequired => "Precondition Required",
+            TooManyRequests => "Too Many Requests",
+            RequestHeaderFieldsTooLarge => "Request Header Fields Too Large",
+            UnavailableForLegalReasons => "Unavailable For Legal Reasons",
+            InternalServerError => "Internal Server Error",
+            NotImplemented => "Not Implemented",
+            BadGateway => "Bad Gateway",
+            ServiceUnavailable => "Service Unavailable",
+            GatewayTimeout => "Gateway Timeout",
+            HttpVersionNotSupported => "HTTP Version Not Supported",
+            VariantAlsoNegotiates => "Variant Also Negotiates",
+            InsufficientStorage => "Insufficient Storage",
+            LoopDetected => "Loop Detected",
+            NotExtended => "Not Extended",
+            NetworkAuthenticationRequired => "Network Authentication Required",
+        }
     }
 }
 
 impl fmt::Display for Code {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "{}", self.canonical_reason())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Code {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        serializer.serialize_u16(u16::from(*self))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Code {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        let code = u16::deserialize(deserializer)?;
+
+        code.try_into()
+            .map_err(|_| serde::de::Error::custom(format!("unknown status code {}", code)))
     }
 }
 
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Self {
-        match err.kind() {
-            io::ErrorKind::AddrInUse => Self::AddrInUse,
-            _ => panic!("unknown io error mapping"),
-        }
+        Self::Io(err)
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::AddrInUse(_, err) | Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", format!("{:?}", self))
+        match self {
+            Self::AddrInUse(addr, _) => write!(f, "address already in use: {}", addr),
+            Self::UnsupportedVersion => f.write_str("UnsupportedVersion"),
+            Self::Io(err) => write!(f, "Io: {}", err),
+            Self::Malformed(kind) => write!(f, "Malformed: {}", kind),
+            #[cfg(feature = "tls")]
+            Self::Tls(message) => write!(f, "Tls: {}", message),
+        }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Version {
-    major: u8,
-    minor: u8,
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Version {
+    pub major: u8,
+    pub minor: u8,
+}
+
+impl Version {
+    pub const HTTP_09: Version = Version { major: 0, minor: 9 };
+    pub const HTTP_10: Version = Version { major: 1, minor: 0 };
+    pub const HTTP_11: Version = Version { major: 1, minor: 1 };
+    pub const HTTP_2: Version = Version { major: 2, minor: 0 };
 }
 
 impl fmt::Display for Version {
@@ -69,8 +462,8 @@ impl fmt::Display for Version {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-enum Method {
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Method {
     Get,
     Head,
     Post,
@@ -80,6 +473,7 @@ enum Method {
     Options,
     Trace,
     Patch,
+    Extension(String),
 }
 
 impl TryFrom<&'_ str> for Method {
@@ -96,368 +490,5672 @@ impl TryFrom<&'_ str> for Method {
             "options" => Self::Options,
             "trace" => Self::Trace,
             "patch" => Self::Patch,
-            _ => Err(Error::Malformed)?
+            _ if is_token(data) => Self::Extension(data.to_string()),
+            _ => Err(Error::Malformed(MalformedKind::Method))?
         })
     }
 }
 
-impl fmt::Display for Method {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", format!("{:?}", &self).to_ascii_uppercase())
+#[cfg(feature = "serde")]
+impl serde::Serialize for Method {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> result::Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
-enum Message {
-    Request {
-        method: Method,
-        target: Target,
-        version: Version,
-        frames: Vec<Frame>,
-    },
-    Response {
-        version: Version,
-        code: Code,
-        frames: Vec<Frame>,
-    },
-}
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Method {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> result::Result<Self, D::Error> {
+        let method = String::deserialize(deserializer)?;
 
-impl Message {
-    pub fn into_bytes(self) -> Vec<u8> {
-        use Message::*;
-        use Frame::*;
-        
-        let mut info = String::new();
+        method
+            .as_str()
+            .try_into()
+            .map_err(|_| serde::de::Error::custom(format!("invalid method {:?}", method)))
+    }
+}
 
-        let begin = match self {
-            Request { method, ref target, version, .. } => {
-                format!("{} {} HTTP/{}\r\n", method, target, version)
-            }
-            Response { version, code, .. } => {
-                format!("HTTP/{} {} {}\r\n", version, u16::from(code), code)
-            }
-        };
+impl str::FromStr for Method {
+    type Err = Error;
 
-        info += &begin;
-        
-        let frames = match self {
-            Request { frames, .. } => { frames }
-            Response { frames, .. } => { frames }
-        };
+    fn from_str(data: &'_ str) -> Result<Self> {
+        data.try_into()
+    }
+}
 
-        let mut payload = vec![];
+impl Method {
+    pub fn is_safe(&self) -> bool {
+        use Method::*;
 
-        for frame in frames {
-            match frame {
-                Headers { headers } => {
-                    for header in headers {
-                        let header = format!("{}: {}\r\n", header.name, header.value);
-                        info += &header;
-                    }
-                }
-                Data { payload: data } => {
-                    payload.extend(data);
-                }
-            }
-        }
+        matches!(self, Get | Head | Options | Trace)
+    }
 
-        let mut response = vec![];
+    pub fn is_idempotent(&self) -> bool {
+        use Method::*;
 
-        response.extend(info.into_bytes());
-        response.extend("\r\n".bytes());
-        response.extend(payload);
+        self.is_safe() || matches!(self, Put | Delete)
+    }
+}
 
-        response
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Extension(method) => write!(f, "{}", method),
+            _ => write!(f, "{}", format!("{:?}", &self).to_ascii_uppercase()),
+        }
     }
+}
 
-    pub fn parse(buffer: &[u8]) -> Result<Self> {
-        let Ok(mut buffer) = String::from_utf8(buffer.iter().cloned().collect()) else {
-            return Err(Error::Malformed);
-        };
+fn parse_params(mut rest: &'_ str) -> Result<Vec<(String, String)>> {
+    let malformed = || Error::Malformed(MalformedKind::Value);
 
-        if buffer.len() == 0 {
-            return Err(Error::Malformed);
-        }
+    let mut params = vec![];
 
-        let mut cursor = buffer.lines();
+    loop {
+        rest = rest.trim_start_matches([' ', '\t', ';']);
 
-        let message: Option<Message> = try {
-            let status_line = cursor.next()?;
+        if rest.is_empty() {
+            break;
+        }
 
-            let (method, target, version) = Self::parse_status_line(&status_line)?;
+        let (name, after) = rest.split_once('=').ok_or_else(malformed)?;
 
-            let mut headers = String::new();
+        let name = name.trim_end();
 
-            while let Some(h) = cursor.next() {
-                if h.len() == 0 {
-                    break;
-                }
+        if !is_token(name) {
+            return Err(malformed());
+        }
 
-                headers += h;
-                headers += "\r\n";
-            }
+        let after = after.trim_start();
 
-            let headers = Self::parse_headers(&headers)?;
+        let value = if let Some(quoted) = after.strip_prefix('"') {
+            let mut value = String::new();
 
-            let mut content_length = None;
+            let mut chars = quoted.char_indices();
 
-            for header in &headers {
-                if header.name == "Content-Length" {
-                    content_length = Some(header.value.parse::<usize>().ok()?);
-                    break;
+            let mut end = None;
+
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => value.push(chars.next().ok_or_else(malformed)?.1),
+                    '"' => {
+                        end = Some(i + 1);
+                        break;
+                    }
+                    c => value.push(c),
                 }
             }
 
-            let headers = Frame::Headers { headers };
+            rest = &quoted[end.ok_or_else(malformed)?..];
 
-            let mut frames = vec![];
+            value
+        } else {
+            let (value, after) = after.split_once(';').unwrap_or((after, ""));
 
-            frames.push(headers);
+            rest = after;
+
+            value.trim_end().to_string()
+        };
 
-            if let Some(l) = content_length {
-                let rest = cursor.collect::<String>();
+        params.push((name.to_ascii_lowercase(), value));
+    }
 
-                buffer = rest.chars().skip(l).collect::<String>();
+    Ok(params)
+}
 
-                cursor = buffer.lines();
+#[derive(Debug, Clone)]
+pub struct MediaType {
+    kind: String,
+    subtype: String,
+    params: Vec<(String, String)>,
+}
 
-                let payload = rest.chars().take(l).collect::<String>().into_bytes();
+impl TryFrom<&'_ str> for MediaType {
+    type Error = Error;
 
-                let data = Frame::Data { payload };
+    fn try_from(data: &'_ str) -> Result<Self> {
+        let (essence, rest) = data.split_once(';').unwrap_or((data, ""));
 
-                frames.push(data);
-            }
+        let malformed = || Error::Malformed(MalformedKind::Value);
 
-            Message::Request {
-                method,
-                target,
-                version,
-                frames,
-            }
-        };
+        let (kind, subtype) = essence.trim().split_once('/').ok_or_else(malformed)?;
 
-        message.ok_or(Error::Malformed)
-    }
+        if !is_token(kind) || !is_token(subtype) {
+            return Err(malformed());
+        }
 
-    fn parse_status_line(info: &'_ str) -> Option<(Method, Target, Version)> {
-        let data = info.split_whitespace().collect::<Vec<_>>();
+        let params = parse_params(rest)?;
 
-        let method = (*data.get(0)?).try_into().ok()?;
+        Ok(Self {
+            kind: kind.to_ascii_lowercase(),
+            subtype: subtype.to_ascii_lowercase(),
+            params,
+        })
+    }
+}
 
-        let target = data.get(1)?.to_string();
+impl MediaType {
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
 
-        let major = data
-            .get(2)?
-            .split("HTTP/")
-            .collect::<Vec<_>>()
-            .get(1)?
-            .split(".")
-            .collect::<Vec<_>>()
-            .get(0)?
-            .parse::<u8>()
-            .unwrap();
+    pub fn subtype(&self) -> &str {
+        &self.subtype
+    }
 
-        let minor = data
-            .get(2)?
-            .split("HTTP/")
-            .collect::<Vec<_>>()
-            .get(1)?
-            .split(".")
-            .collect::<Vec<_>>()
-            .get(1)?
-            .parse::<u8>()
-            .unwrap();
+    pub fn is(&self, essence: &str) -> bool {
+        essence
+            .split_once('/')
+            .is_some_and(|(kind, subtype)| {
+                kind.eq_ignore_ascii_case(&self.kind) && subtype.eq_ignore_ascii_case(&self.subtype)
+            })
+    }
 
-        let version = Version { major, minor };
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
 
-        Some((method, target, version))
+    pub fn params(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(key, value)| (key.as_str(), value.as_str()))
     }
 
-    fn parse_headers(info: &'_ str) -> Option<Vec<Header>> {
-        let mut headers = vec![];
+    pub fn charset(&self) -> Option<&str> {
+        self.param("charset")
+    }
 
-        for line in info.lines() {
-            let name = line.split(":").take(1).collect::<String>();
+    pub fn boundary(&self) -> Option<&str> {
+        self.param("boundary")
+    }
+}
 
-            let value = line
-                .split(":")
-                .skip(1)
-                .collect::<String>()
-                .trim_start()
-                .to_string();
+impl fmt::Display for MediaType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.kind, self.subtype)?;
 
-            if name.len() == 0 || value.len() == 0 {
-                None?
+        for (name, value) in &self.params {
+            if is_token(value) {
+                write!(f, "; {}={}", name, value)?;
+            } else {
+                write!(f, "; {}={}", name, quote(value))?;
             }
-
-            headers.push(Header { name, value });
         }
 
-        Some(headers)
+        Ok(())
     }
 }
 
-struct MessageBuilder {
-    version: Version,
-    code: Code,
-    headers: Vec<Header>,
-    payload: Vec<u8>,
+pub struct Part {
+    headers: Headers,
+    disposition: Vec<(String, String)>,
+    data: Vec<u8>,
 }
 
-impl MessageBuilder {
-    pub fn new() -> Self {
-        Self {
-            version: Version { major: 1, minor: 1 },
-            code: Code::Success,
-            headers: vec![],
-            payload: vec![],
-        }
+impl Part {
+    pub fn name(&self) -> Option<&str> {
+        self.disposition_param("name")
     }
 
-    pub fn version(self, version: Version) -> Self {
-        Self { version, ..self }
+    pub fn filename(&self) -> Option<&str> {
+        self.disposition_param("filename")
     }
 
-    pub fn code(self, code: Code) -> Self {
-        Self { code, ..self }
+    pub fn content_type(&self) -> Option<MediaType> {
+        self.headers.get("Content-Type")?.try_into().ok()
     }
 
-    pub fn header(self, header: Header) -> Self {  
-        let Self {
-            mut headers,
-            ..
-        } = self;
-        
-        headers.push(header);
-
-        Self { headers, ..self }
+    pub fn headers(&self) -> &Headers {
+        &self.headers
     }
 
-    pub fn body(mut self, body: Body<'_>) -> Self {
-        let Self {
-            mut payload,
-            ..
-        } = self;
-        
-        payload.extend(body.bytes());
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
 
-        Self { payload, ..self }
+    fn disposition_param(&self, name: &str) -> Option<&str> {
+        self.disposition
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
     }
+}
 
-    pub fn build(self) -> Message {
-        let MessageBuilder {
-            version,
-            code,
-            headers,
-            payload,
-        } = self;
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
 
-        let mut frames = vec![];
+pub enum Message {
+    Request {
+        method: Method,
+        target: Target,
+        version: Version,
+        frames: Vec<Frame>,
+    },
+    Response {
+        version: Version,
+        code: Code,
+        frames: Vec<Frame>,
+    },
+}
 
-        let headers = Frame::Headers { headers };
+const DAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
 
-        frames.push(headers);
+pub fn http_date(time: time::SystemTime) -> String {
+    let seconds = time
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs());
 
-        let data = Frame::Data { payload };
+    let days = seconds / 86400;
+    let seconds = seconds % 86400;
 
-        frames.push(data);
+    let weekday = DAYS[((days + 4) % 7) as usize];
 
-        Message::Response {
-            version,
-            code,
-            frames,
-        }
-    }
-}
+    // Civil-from-days conversion, counting eras of 400 years from 0000-03-01.
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
 
-struct Header {
-    pub name: String,
-    pub value: String,
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        weekday,
+        day,
+        MONTHS[(month - 1) as usize],
+        year,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+    )
 }
 
-macro_rules! headers { 
-    ($builder: ident, $($name: literal: $value: expr),*) => {
-        $($builder = $builder.header(Header { name: format!("{}", { $name }), value: format!("{}", { $value }) });)*
+pub fn parse_http_date(data: &'_ str) -> Option<time::SystemTime> {
+    let (weekday, date) = data.trim().split_once(", ")?;
+
+    let [day, month, year, clock, "GMT"] = date.split(' ').collect::<Vec<_>>()[..] else {
+        None?
     };
-}
 
-macro_rules! body { 
-    ($builder: ident, $body: expr) => {
-        $builder = $builder.body($body)
+    let [hour, minute, second] = clock.split(':').collect::<Vec<_>>()[..] else {
+        None?
     };
-}
 
-enum Frame {
-    Headers { headers: Vec<Header> },
-    Data { payload: Vec<u8> },
-}
+    let digits = |field: &str, width: usize| -> Option<u64> {
+        (field.len() == width && field.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| field.parse().ok())?
+    };
 
-pub struct Server {
-    listener: net::TcpListener,
-}
+    let (day, year) = (digits(day, 2)?, digits(year, 4)? as i64);
+    let (hour, minute, second) = (digits(hour, 2)?, digits(minute, 2)?, digits(second, 2)?);
 
-impl Server {
-    pub fn bind(port: u16) -> Result<Self> {
-        let local_host = String::from(LOCAL_HOST);
-        let addr = local_host + ":" + &port.to_string();
+    let month = MONTHS.iter().position(|name| *name == month)? as i64 + 1;
+
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        None?
+    }
 
-        let listener = net::TcpListener::bind(addr).map_err(|e| Error::from(e))?;
+    // Days-from-civil conversion, the inverse of the one in `http_date`.
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = u64::try_from(era * 146097 + doe - 719468).ok()?;
 
-        Ok(Self { listener })
+    if DAYS[((days + 4) % 7) as usize] != weekday {
+        None?
     }
 
-    pub fn respond(&mut self) {
-        use io::{ Write, Read };
-        
-        let Some((mut stream, addr)) = self.listener.accept().ok() else {
-            return;
-        };
+    let seconds = days * 86400 + hour * 3600 + minute * 60 + second;
 
-        stream.set_nonblocking(true).expect("failed to set stream to non-blocking");
-        
-        let mut buffer = vec![];
+    Some(time::UNIX_EPOCH + time::Duration::from_secs(seconds))
+}
 
-        let mut octet = [0; 8];
+pub fn static_file(root: impl AsRef<path::Path>, target: &str) -> Message {
+    let Some(decoded) = percent_decode(target.split_once('?').map_or(target, |(path, _)| path))
+    else {
+        return Message::error(Code::BadRequest);
+    };
 
-        loop {
-            let read = stream.read(&mut octet);
+    let Ok(decoded) = String::from_utf8(decoded) else {
+        return Message::error(Code::BadRequest);
+    };
 
-            if let Ok(length) = read {
-                if length == 0 {
-                    break;
-                }
+    let mut file = root.as_ref().to_path_buf();
 
-                for i in 0..length {
-                    buffer.push(octet[i]);
-                }
-            } else if let Err(e) = read {
-                break;
+    for segment in decoded.split('/') {
+        match segment {
+            "" | "." => continue,
+            ".." => return Message::error(Code::Forbidden),
+            _ if segment.contains(['\\', '\0']) || segment.ends_with(':') => {
+                return Message::error(Code::Forbidden)
             }
+            _ => file.push(segment),
         }
+    }
 
-        let request = Message::parse(&buffer);
+    if file.is_dir() {
+        file.push("index.html");
+    }
 
-        let body = "
-            <html>
-                <p>Hello, world!</p>
-            </html>
-        ";
+    let data = match fs::read(&file) {
+        Ok(data) => data,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            return Message::error(Code::NotFound)
+        }
+        Err(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+            return Message::error(Code::Forbidden)
+        }
+        Err(_) => return Message::error(Code::InternalServerError),
+    };
+
+    let extension = file.extension().and_then(|extension| extension.to_str()).unwrap_or_default();
+
+    let mut message = MessageBuilder { payload: data, ..MessageBuilder::new() };
+
+    headers! { message, "Content-Type": mime_from_extension(extension) };
+
+    if let Ok(modified) = fs::metadata(&file).and_then(|metadata| metadata.modified()) {
+        headers! { message, "Last-Modified": http_date(modified) };
+    }
+
+    message.build()
+}
+
+pub fn mime_from_extension(extension: &'_ str) -> &'static str {
+    let extension = extension.strip_prefix('.').unwrap_or(extension);
+
+    match extension.to_ascii_lowercase().as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "wasm" => "application/wasm",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        _ => "application/octet-stream",
+    }
+}
+
+fn is_token(data: &'_ str) -> bool {
+    !data.is_empty()
+        && data
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn quote(data: &'_ str) -> String {
+    let mut quoted = String::from('"');
+
+    for c in data.chars().filter(|c| !c.is_control()) {
+        if matches!(c, '"' | '\\') {
+            quoted.push('\\');
+        }
+
+        quoted.push(c);
+    }
+
+    quoted.push('"');
+
+    quoted
+}
+
+fn percent_decode(data: &'_ str) -> Option<Vec<u8>> {
+    let mut decoded = vec![];
+
+    let mut bytes = data.bytes();
+
+    while let Some(b) = bytes.next() {
+        if b != b'%' {
+            decoded.push(b);
+            continue;
+        }
+
+        let high = (bytes.next()? as char).to_digit(16)?;
+        let low = (bytes.next()? as char).to_digit(16)?;
+
+        decoded.push((high * 16 + low) as u8);
+    }
+
+    Some(decoded)
+}
+
+fn decode_component(data: &'_ str) -> String {
+    let data = data.replace('+', " ");
+
+    match percent_decode(&data) {
+        Some(decoded) => String::from_utf8_lossy(&decoded).into_owned(),
+        None => data,
+    }
+}
+
+fn base64_decode(data: &'_ str) -> Option<Vec<u8>> {
+    let value = |b: u8| -> Option<u32> {
+        Some(match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => None?,
+        } as u32)
+    };
+
+    let data = data.as_bytes();
+
+    if !data.len().is_multiple_of(4) {
+        None?
+    }
+
+    let mut decoded = Vec::with_capacity(data.len() / 4 * 3);
+
+    for (i, quad) in data.chunks(4).enumerate() {
+        let padding = quad.iter().rev().take_while(|&&b| b == b'=').count();
+
+        if padding > 2 || (padding > 0 && i + 1 != data.len() / 4) {
+            None?
+        }
+
+        let mut bits = 0;
+
+        for &b in &quad[..4 - padding] {
+            bits = bits << 6 | value(b)?;
+        }
+
+        bits <<= 6 * padding;
+
+        decoded.extend(&bits.to_be_bytes()[1..4 - padding]);
+    }
+
+    Some(decoded)
+}
+
+pub enum ParseState {
+    Complete(Message, usize),
+    Partial,
+    Error(Error),
+}
+
+/// A request whose method, target, headers and body borrow from the buffer it was parsed from.
+pub struct RequestRef<'a> {
+    method: &'a str,
+    target: &'a str,
+    version: Version,
+    headers: Vec<(&'a str, &'a str)>,
+    body: borrow::Cow<'a, [u8]>,
+}
+
+impl<'a> RequestRef<'a> {
+    pub fn method(&self) -> &'a str {
+        self.method
+    }
+
+    pub fn target(&self) -> &'a str {
+        self.target
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &[(&'a str, &'a str)] {
+        &self.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    }
+
+    /// The body, borrowed unless it had to be decoded from chunks.
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn to_owned(&self) -> Message {
+        let mut headers = Headers::new();
+
+        for (name, value) in &self.headers {
+            headers.append(Header { name: name.to_string(), value: value.to_string() });
+        }
+
+        let framed =
+            self.header("Content-Length").is_some() || self.header("Transfer-Encoding").is_some();
+
+        let mut frames = vec![Frame::Headers { headers }];
+
+        if framed {
+            frames.push(Frame::Data { payload: self.body.to_vec() });
+        }
+
+        let method = self.method.try_into();
+
+        Message::Request {
+            method: method.unwrap_or_else(|_| Method::Extension(self.method.into())),
+            target: self.target.to_string(),
+            version: self.version,
+            frames,
+        }
+    }
+}
+
+enum StartLine {
+    Request {
+        method: Method,
+        target: Target,
+        version: Version,
+    },
+    Response {
+        version: Version,
+        code: Code,
+    },
+}
+
+impl StartLine {
+    fn into_message(self, frames: Vec<Frame>) -> Message {
+        match self {
+            StartLine::Request { method, target, version } => Message::Request {
+                method,
+                target,
+                version,
+                frames,
+            },
+            StartLine::Response { version, code } => Message::Response {
+                version,
+                code,
+                frames,
+            },
+        }
+    }
+}
+
+enum Framing {
+    Length(usize),
+    Chunked { remaining: usize, done: bool },
+}
+
+/// A message head split into its start line and fields, borrowed from the buffer; every parser
+/// reads heads through this so they agree on where a message ends.
+struct RawHead<'a> {
+    start: &'a str,
+    fields: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> RawHead<'a> {
+    fn scan(head: &'a [u8]) -> Parsed<Self> {
+        let info = str::from_utf8(head).map_err(|_| MalformedKind::Header)?;
+
+        let (start, fields) = info.split_once("\r\n").unwrap_or((info, ""));
+
+        let fields = Message::parse_fields(fields)?;
+
+        Ok(Self { start, fields })
+    }
+
+    /// How the body is delimited, `None` when the head declares no body at all.
+    fn framing(&self) -> Parsed<Option<Framing>> {
+        // Only the final transfer coding decides whether the body is chunked.
+        let coding = self
+            .fields
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Transfer-Encoding"))
+            .flat_map(|(_, value)| value.split(','))
+            .last();
+
+        if coding.is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked")) {
+            return Ok(Some(Framing::Chunked { remaining: 0, done: false }));
+        }
+
+        match self.field("Content-Length") {
+            Some(length) => {
+                let length = Message::parse_length(length)?;
+
+                Ok(Some(Framing::Length(length)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn field(&self, name: &str) -> Option<&'a str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    }
+}
+
+pub struct BodyReader<R> {
+    reader: R,
+    buffer: Vec<u8>,
+    framing: Framing,
+    limit: usize,
+    exceeded: bool,
+    max_header_bytes: usize,
+}
+
+impl<R: io::Read> BodyReader<R> {
+    fn fill(&mut self) -> io::Result<()> {
+        let mut octets = [0; READ_BUFFER_BYTES];
+
+        let length = self.reader.read(&mut octets)?;
+
+        if length == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        self.buffer.extend_from_slice(&octets[..length]);
+
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        loop {
+            if let Some(end) = find_bytes(&self.buffer, b"\r\n") {
+                let line = self.buffer.drain(..end + 2).take(end).collect();
+
+                return Ok(line);
+            }
+
+            if self.buffer.len() > self.max_header_bytes {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+
+            self.fill()?;
+        }
+    }
+
+    fn read_exact_chunk(&mut self, out: &mut [u8], remaining: usize) -> io::Result<usize> {
+        let wanted = out.len().min(remaining);
+
+        if wanted == 0 {
+            return Ok(0);
+        }
+
+        if self.buffer.is_empty() {
+            let length = self.reader.read(&mut out[..wanted])?;
+
+            if length == 0 {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+
+            return Ok(length);
+        }
+
+        let length = wanted.min(self.buffer.len());
+
+        out[..length].copy_from_slice(&self.buffer[..length]);
+
+        self.buffer.drain(..length);
+
+        Ok(length)
+    }
+}
+
+impl<R: io::Read> io::Read for BodyReader<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        match self.framing {
+            Framing::Length(remaining) => {
+                let length = self.read_exact_chunk(out, remaining)?;
+
+                self.framing = Framing::Length(remaining - length);
+
+                Ok(length)
+            }
+            Framing::Chunked { done: true, .. } => Ok(0),
+            Framing::Chunked { remaining: 0, .. } => {
+                let line = self.read_line()?;
+
+                let size = str::from_utf8(&line)
+                    .ok()
+                    .and_then(|line| line.split(';').next())
+                    .and_then(|size| usize::from_str_radix(size.trim(), 16).ok())
+                    .ok_or(io::ErrorKind::InvalidData)?;
+
+                if size > self.limit {
+                    self.exceeded = true;
+
+                    return Err(io::ErrorKind::InvalidData.into());
+                }
+
+                self.limit -= size;
+
+                if size == 0 {
+                    while !self.read_line()?.is_empty() {}
+
+                    self.framing = Framing::Chunked { remaining: 0, done: true };
+
+                    return Ok(0);
+                }
+
+                self.framing = Framing::Chunked { remaining: size, done: false };
+
+                self.read(out)
+            }
+            Framing::Chunked { remaining, .. } => {
+                let length = self.read_exact_chunk(out, remaining)?;
+
+                if remaining == length && !self.read_line()?.is_empty() {
+                    return Err(io::ErrorKind::InvalidData.into());
+                }
+
+                self.framing = Framing::Chunked { remaining: remaining - length, done: false };
+
+                Ok(length)
+            }
+        }
+    }
+}
+
+impl From<Code> for Message {
+    fn from(code: Code) -> Self {
+        Message::error(code)
+    }
+}
+
+impl Message {
+    pub fn error(code: Code) -> Self {
+        let body = format!("<h1>{} {}</h1>", u16::from(code), code.canonical_reason());
+
+        let mut message = MessageBuilder::new().code(code);
+
+        headers! { message, "Content-Type": "text/html; charset=utf-8" };
+
+        body! { message, &body };
+
+        message.build()
+    }
+
+    pub fn partial(mut self, request: &Message) -> Self {
+        let applicable = matches!(self.code(), Some(Code::Success))
+            && matches!(request.method(), Some(Method::Get | Method::Head))
+            && !self.headers().contains("Transfer-Encoding")
+            && !self.is_streamed();
+
+        let Some(range) = request.range().filter(|_| applicable) else {
+            return self;
+        };
+
+        let length = self.body().len();
+
+        let bounds = match range {
+            (Some(start), end) if start < length => {
+                Some((start, end.map_or(length - 1, |end| end.min(length - 1))))
+            }
+            (None, Some(suffix)) if suffix > 0 && length > 0 => {
+                Some((length - suffix.min(length), length - 1))
+            }
+            _ => None,
+        };
+
+        let (status, payload, content_range) = match bounds {
+            Some((start, end)) => (
+                Code::PartialContent,
+                self.body()[start..=end].to_vec(),
+                format!("bytes {}-{}/{}", start, end, length),
+            ),
+            None => (Code::RangeNotSatisfiable, vec![], format!("bytes */{}", length)),
+        };
+
+        let content_length = payload.len().to_string();
+
+        if let Message::Response { code, frames, .. } = &mut self {
+            *code = status;
+
+            frames.retain(|frame| matches!(frame, Frame::Headers { .. }));
+
+            frames.push(Frame::Data { payload });
+        }
+
+        let headers = self.headers_mut();
+
+        headers.insert("Content-Range", content_range);
+
+        headers.insert("Content-Length", content_length);
+
+        self
+    }
+
+    /// Builds a redirect to `location`, or `None` when `code` is not 301, 302, 303, 307 or 308.
+    pub fn redirect(code: Code, location: &str) -> Option<Self> {
+        if !matches!(u16::from(code), 301 | 302 | 303 | 307 | 308) {
+            return None;
+        }
+
+        let mut message = Self::error(code);
+
+        let location = location.chars().filter(|c| !c.is_control()).collect();
+
+        message.push_header(Header { name: "Location".into(), value: location });
+
+        Some(message)
+    }
+
+    pub fn unauthorized(realm: &str) -> Self {
+        let mut message = Self::error(Code::Unauthorized);
+
+        let challenge = format!("Basic realm={}", quote(realm));
+
+        message.push_header(Header { name: "WWW-Authenticate".into(), value: challenge });
+
+        message
+    }
+
+    /// Serializes the message, reading any streamed body to the end.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.encode(true).into_bytes()
+    }
+
+    pub fn into_head_bytes(self) -> Vec<u8> {
+        self.encode(false).into_bytes()
+    }
+
+    /// Writes the message, copying a streamed body straight through rather than buffering it.
+    pub fn write_to<W: io::Write>(self, writer: &mut W) -> io::Result<()> {
+        self.encode(true).write_to(writer).map(|_| ())
+    }
+
+    fn encode(self, body: bool) -> Encoded {
+        use Message::*;
+        use Frame::*;
+        
+        let mut info = String::new();
+
+        let begin = match self {
+            Request { ref method, ref target, version, .. } => {
+                format!("{} {} HTTP/{}\r\n", method, target, version)
+            }
+            Response { version, code, .. } => {
+                format!("HTTP/{} {} {}\r\n", version, u16::from(code), code)
+            }
+        };
+
+        info += &begin;
+        
+        let frames = match self {
+            Request { frames, .. } => { frames }
+            Response { frames, .. } => { frames }
+        };
+
+        let mut payload = vec![];
+
+        let mut chunked = false;
+
+        let mut reader = None;
+
+        let mut trailers = None;
+
+        for frame in frames {
+            match frame {
+                Headers { headers } => {
+                    if let Some(encoding) = headers.get("Transfer-Encoding") {
+                        chunked |= encoding.to_ascii_lowercase().contains("chunked");
+                    }
+
+                    for header in headers {
+                        let header = format!("{}: {}\r\n", header.name, header.value);
+                        info += &header;
+                    }
+                }
+                Data { payload: data } if chunked => {
+                    if !data.is_empty() {
+                        payload.extend(format!("{:X}\r\n", data.len()).bytes());
+                        payload.extend(data);
+                        payload.extend("\r\n".bytes());
+                    }
+                }
+                Data { payload: data } => {
+                    payload.extend(data);
+                }
+                Reader { reader: stream } => {
+                    reader = Some(stream);
+                }
+                Trailers { headers } => {
+                    trailers = Some(headers);
+                }
+            }
+        }
+
+        let mut response = vec![];
+
+        response.extend(info.into_bytes());
+        response.extend("\r\n".bytes());
+
+        if body {
+            response.extend(payload);
+        }
+
+        Encoded {
+            bytes: response,
+            reader: reader.filter(|_| body),
+            chunked: chunked && body,
+            trailers: trailers.unwrap_or_default(),
+        }
+    }
+
+    fn is_streamed(&self) -> bool {
+        self.frames().iter().any(|frame| matches!(frame, Frame::Reader { .. }))
+    }
+
+    /// Returns the request method, or `None` for a response.
+    ///
+    /// ```
+    /// use http::{Message, Method};
+    ///
+    /// let request = Message::parse(b"DELETE /users/7 HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+    ///
+    /// assert!(matches!(request.method(), Some(Method::Delete)));
+    /// ```
+    pub fn method(&self) -> Option<&Method> {
+        match self {
+            Message::Request { method, .. } => Some(method),
+            Message::Response { .. } => None,
+        }
+    }
+
+    /// Returns the request target exactly as received, query included.
+    ///
+    /// ```
+    /// use http::Message;
+    ///
+    /// let request = Message::parse(b"GET /search?q=rust HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+    ///
+    /// assert_eq!(request.target(), Some("/search?q=rust"));
+    /// ```
+    pub fn target(&self) -> Option<&str> {
+        match self {
+            Message::Request { target, .. } => Some(target),
+            Message::Response { .. } => None,
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        let target = self.target().unwrap_or_default();
+
+        target.split_once('?').map_or(target, |(path, _)| path)
+    }
+
+    pub fn decoded_path(&self) -> Result<String> {
+        let malformed = || Error::Malformed(MalformedKind::Target);
+
+        let decoded = percent_decode(self.path()).ok_or_else(malformed)?;
+
+        String::from_utf8(decoded).map_err(|_| malformed())
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        let (_, query) = self.target()?.split_once('?')?;
+
+        Some(query)
+    }
+
+    pub fn query_pairs(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        self.query()
+            .unwrap_or_default()
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+                (decode_component(key), decode_component(value))
+            })
+    }
+
+    /// Returns the protocol version of either a request or a response.
+    ///
+    /// ```
+    /// use http::{Message, Version};
+    ///
+    /// let request = Message::parse(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+    ///
+    /// assert_eq!(request.version(), Version::HTTP_10);
+    /// ```
+    pub fn version(&self) -> Version {
+        match self {
+            Message::Request { version, .. } => *version,
+            Message::Response { version, .. } => *version,
+        }
+    }
+
+    pub fn code(&self) -> Option<Code> {
+        match self {
+            Message::Request { .. } => None,
+            Message::Response { code, .. } => Some(*code),
+        }
+    }
+
+    /// Returns the message headers, empty if there are none.
+    ///
+    /// ```
+    /// use http::Message;
+    ///
+    /// let request = Message::parse(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n").unwrap();
+    ///
+    /// assert_eq!(request.headers().get("host"), Some("example.com"));
+    /// ```
+    pub fn headers(&self) -> &Headers {
+        static EMPTY: Headers = Headers { headers: Vec::new() };
+
+        for frame in self.frames() {
+            if let Frame::Headers { headers } = frame {
+                return headers;
+            }
+        }
+
+        &EMPTY
+    }
+
+    /// Returns the message body, empty if there is none.
+    ///
+    /// ```
+    /// use http::Message;
+    ///
+    /// let request = Message::parse(
+    ///     b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello",
+    /// ).unwrap();
+    ///
+    /// assert_eq!(request.body(), b"hello");
+    /// ```
+    pub fn body(&self) -> &[u8] {
+        for frame in self.frames() {
+            if let Frame::Data { payload } = frame {
+                return payload;
+            }
+        }
+
+        &[]
+    }
+
+    /// Returns the trailer fields that followed a chunked body, if any were sent.
+    pub fn trailers(&self) -> Option<&Headers> {
+        self.frames().iter().find_map(|frame| match frame {
+            Frame::Trailers { headers } => Some(headers),
+            _ => None,
+        })
+    }
+
+    pub fn content_type(&self) -> Option<MediaType> {
+        self.headers().get("Content-Type")?.try_into().ok()
+    }
+
+    pub fn form_data(&self) -> Option<Vec<(String, String)>> {
+        if !self.content_type()?.is("application/x-www-form-urlencoded") {
+            return None;
+        }
+
+        let body = str::from_utf8(self.body()).ok()?;
+
+        let pairs = body
+            .split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+
+                (decode_component(key), decode_component(value))
+            })
+            .collect();
+
+        Some(pairs)
+    }
+
+    pub fn multipart(&self) -> Option<Vec<Part>> {
+        let content_type = self.content_type()?;
+
+        if !content_type.is("multipart/form-data") {
+            return None;
+        }
+
+        let delimiter = format!("--{}", content_type.boundary()?).into_bytes();
+
+        let separator = [b"\r\n".as_slice(), &delimiter].concat();
+
+        let body = self.body();
+
+        let mut cursor = find_bytes(body, &delimiter)? + delimiter.len();
+
+        let mut parts = vec![];
+
+        loop {
+            let rest = &body[cursor..];
+
+            if rest.starts_with(b"--") {
+                break;
+            }
+
+            let padding = rest.iter().take_while(|b| **b == b' ' || **b == b'\t').count();
+
+            if !rest[padding..].starts_with(b"\r\n") {
+                None?
+            }
+
+            cursor += padding + 2;
+
+            let end = cursor + find_bytes(&body[cursor..], &separator)?;
+
+            let part = &body[cursor..end];
+
+            let (head, data) = match part.strip_prefix(b"\r\n") {
+                Some(data) => ("", data),
+                None => {
+                    let split = find_bytes(part, b"\r\n\r\n")?;
+
+                    (str::from_utf8(&part[..split]).ok()?, &part[split + 4..])
+                }
+            };
+
+            let headers = Self::parse_headers(head)?;
+
+            let disposition = match headers.get("Content-Disposition") {
+                Some(value) => {
+                    let (_, params) = value.split_once(';').unwrap_or((value, ""));
+
+                    parse_params(params).ok()?
+                }
+                None => vec![],
+            };
+
+            parts.push(Part { headers, disposition, data: data.to_vec() });
+
+            cursor = end + separator.len();
+        }
+
+        Some(parts)
+    }
+
+    pub fn basic_auth(&self) -> Option<(String, String)> {
+        let (scheme, credentials) = self.headers().get("Authorization")?.trim().split_once(' ')?;
+
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            None?
+        }
+
+        let decoded = String::from_utf8(base64_decode(credentials.trim())?).ok()?;
+
+        let (user, password) = decoded.split_once(':')?;
+
+        Some((user.to_string(), password.to_string()))
+    }
+
+    pub fn range(&self) -> Option<(Option<usize>, Option<usize>)> {
+        let (unit, spec) = self.headers().get("Range")?.trim().split_once('=')?;
+
+        if !unit.trim().eq_ignore_ascii_case("bytes") || spec.contains(',') {
+            None?
+        }
+
+        let (start, end) = spec.trim().split_once('-')?;
+
+        let bound = |data: &'_ str| -> Option<Option<usize>> {
+            match data.trim() {
+                "" => Some(None),
+                data if data.bytes().all(|b| b.is_ascii_digit()) => Some(Some(data.parse().ok()?)),
+                _ => None,
+            }
+        };
+
+        match (bound(start)?, bound(end)?) {
+            (None, None) => None,
+            (Some(start), Some(end)) if start > end => None,
+            range => Some(range),
+        }
+    }
+
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        self.header_values("Cookie")
+            .iter()
+            .flat_map(|value| value.split(';'))
+            .filter_map(|pair| {
+                let (name, value) = pair.split_once('=')?;
+
+                let (name, value) = (name.trim(), value.trim());
+
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|value| value.strip_suffix('"'))
+                    .unwrap_or(value);
+
+                (!name.is_empty()).then(|| (name.to_string(), value.to_string()))
+            })
+            .collect()
+    }
+
+    pub fn accepts_encoding(&self, coding: &str) -> bool {
+        self.header_values("Accept-Encoding")
+            .iter()
+            .flat_map(|value| value.split(','))
+            .any(|item| {
+                let (name, params) = item.split_once(';').unwrap_or((item, ""));
+
+                let name = name.trim();
+
+                let quality = params
+                    .split(';')
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .next()
+                    .map_or(1.0, |q| q.trim().parse::<f32>().unwrap_or(0.0));
+
+                (name.eq_ignore_ascii_case(coding) || name == "*") && quality > 0.0
+            })
+    }
+
+    #[cfg(feature = "gzip")]
+    pub fn gzip(&mut self) -> bool {
+        use io::Write;
+
+        let length = self
+            .frames()
+            .iter()
+            .map(|frame| match frame {
+                Frame::Data { payload } => payload.len(),
+                _ => 0,
+            })
+            .sum::<usize>();
+
+        let compressed = self.content_type().is_some_and(|media| {
+            matches!(media.kind(), "image" | "audio" | "video") && media.subtype() != "svg+xml"
+                || matches!(
+                    (media.kind(), media.subtype()),
+                    ("application", "zip" | "gzip" | "x-gzip" | "zstd" | "x-7z-compressed")
+                        | ("font", "woff" | "woff2")
+                )
+        });
+
+        let headers = self.headers();
+
+        if length < MIN_COMPRESS_BYTES
+            || compressed
+            || self.is_streamed()
+            || headers.contains("Content-Encoding")
+            || headers.contains("Content-Range")
+        {
+            return false;
+        }
+
+        let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+
+        for frame in self.frames() {
+            if let Frame::Data { payload } = frame {
+                if encoder.write_all(payload).is_err() {
+                    return false;
+                }
+            }
+        }
+
+        let Ok(payload) = encoder.finish() else {
+            return false;
+        };
+
+        let headers = self.headers_mut();
+
+        if headers.contains("Content-Length") {
+            headers.insert("Content-Length", payload.len().to_string());
+        }
+
+        headers.insert("Content-Encoding", "gzip");
+
+        headers.append(Header { name: "Vary".into(), value: "Accept-Encoding".into() });
+
+        let frames = self.frames_mut();
+
+        frames.retain(|frame| matches!(frame, Frame::Headers { .. } | Frame::Trailers { .. }));
+
+        frames.push(Frame::Data { payload });
+
+        true
+    }
+
+    pub fn is_not_modified(&self, etag: Option<&str>, last_modified: Option<time::SystemTime>) -> bool {
+        if !matches!(self.method(), Some(Method::Get | Method::Head)) {
+            return false;
+        }
+
+        let weak = |tag: &'_ str| tag.trim().trim_start_matches("W/").to_string();
+
+        let matches = self.header_values("If-None-Match");
+
+        if !matches.is_empty() {
+            let Some(etag) = etag else {
+                return false;
+            };
+
+            return matches
+                .iter()
+                .flat_map(|value| value.split(','))
+                .any(|tag| tag.trim() == "*" || weak(tag) == weak(etag));
+        }
+
+        let since = self.headers().get("If-Modified-Since").and_then(parse_http_date);
+
+        match (since, last_modified) {
+            (Some(since), Some(modified)) => {
+                let seconds = |time: time::SystemTime| {
+                    time.duration_since(time::UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs())
+                };
+
+                seconds(modified) <= seconds(since)
+            }
+            _ => false,
+        }
+    }
+
+    pub fn iter_headers(&self) -> impl Iterator<Item = &Header> {
+        self.frames().iter().flat_map(|frame| match frame {
+            Frame::Headers { headers } => headers.iter(),
+            Frame::Data { .. } | Frame::Reader { .. } | Frame::Trailers { .. } => [].iter(),
+        })
+    }
+
+    pub fn header_values(&self, name: &str) -> Vec<&str> {
+        let mut values = vec![];
+
+        for frame in self.frames() {
+            if let Frame::Headers { headers } = frame {
+                values.extend(headers.get_all(name));
+            }
+        }
+
+        values
+    }
+
+    fn has_token(&self, name: &str, token: &str) -> bool {
+        self.header_values(name)
+            .iter()
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    fn keep_alive(&self) -> bool {
+        let Message::Request { version, .. } = self else {
+            return false;
+        };
+
+        if *version < Version::HTTP_11 {
+            self.has_token("Connection", "keep-alive")
+        } else {
+            !self.has_token("Connection", "close")
+        }
+    }
+
+    fn push_header(&mut self, header: Header) {
+        self.headers_mut().append(header);
+    }
+
+    fn headers_mut(&mut self) -> &mut Headers {
+        let frames = self.frames_mut();
+
+        let position = frames
+            .iter()
+            .position(|frame| matches!(frame, Frame::Headers { .. }));
+
+        let index = position.unwrap_or_else(|| {
+            frames.insert(0, Frame::Headers { headers: Headers::new() });
+            0
+        });
+
+        let Frame::Headers { headers } = &mut frames[index] else {
+            unreachable!("frame at index is a headers frame");
+        };
+
+        headers
+    }
+
+    fn frames_mut(&mut self) -> &mut Vec<Frame> {
+        use Message::*;
+
+        match self {
+            Request { frames, .. } => frames,
+            Response { frames, .. } => frames,
+        }
+    }
+
+    fn frames(&self) -> &[Frame] {
+        use Message::*;
+
+        match self {
+            Request { frames, .. } => frames,
+            Response { frames, .. } => frames,
+        }
+    }
+
+    /// Parses one complete message; versions other than 0.9, 1.0, 1.1 and 2.0 are unsupported.
+    pub fn parse(buffer: &[u8]) -> Result<Self> {
+        Self::parse_with_len(buffer).map(|(message, _)| message)
+    }
+
+    /// Parses one message and returns it with the number of bytes it occupied, body included.
+    pub fn parse_with_len(buffer: &[u8]) -> Result<(Self, usize)> {
+        let message: Parsed<(Message, usize)> = try {
+            let end = find_bytes(buffer, b"\r\n\r\n").ok_or(MalformedKind::Incomplete)?;
+
+            let head = RawHead::scan(&buffer[..end])?;
+
+            let (start, headers) = Self::parse_raw_head(&head)?;
+
+            let body = &buffer[end + 4..];
+
+            let headers = Frame::Headers { headers };
+
+            let mut frames = vec![];
+
+            frames.push(headers);
+
+            let mut consumed = end + 4;
+
+            match head.framing()? {
+                Some(Framing::Chunked { .. }) => {
+                    let chunked = Self::parse_chunked(body)?;
+
+                    let (payload, trailers, length) = chunked.ok_or(MalformedKind::Incomplete)?;
+
+                    let data = Frame::Data { payload };
+
+                    frames.push(data);
+
+                    if !trailers.is_empty() {
+                        frames.push(Frame::Trailers { headers: trailers });
+                    }
+
+                    consumed += length;
+                }
+                Some(Framing::Length(l)) => {
+                    let payload = body.get(..l).ok_or(MalformedKind::Incomplete)?.to_vec();
+
+                    let data = Frame::Data { payload };
+
+                    frames.push(data);
+
+                    consumed += l;
+                }
+                None => {}
+            }
+
+            (start.into_message(frames), consumed)
+        };
+
+        message.map_err(|kind| Self::head_error(buffer, kind))
+    }
+
+    /// Parses a message that may still be arriving, telling truncated input apart from bad input.
+    pub fn parse_incremental(buffer: &[u8], max_header_bytes: usize) -> ParseState {
+        let Some(head) = find_bytes(buffer, b"\r\n\r\n") else {
+            if buffer.len() > max_header_bytes {
+                return ParseState::Error(Error::Malformed(MalformedKind::HeaderTooLarge));
+            }
+
+            return ParseState::Partial;
+        };
+
+        if head + 4 > max_header_bytes {
+            return ParseState::Error(Error::Malformed(MalformedKind::HeaderTooLarge));
+        }
+
+        if let Err(kind) = Self::parse_head(&buffer[..head]) {
+            return ParseState::Error(Self::head_error(&buffer[..head], kind));
+        }
+
+        match Self::expected_len(buffer) {
+            Ok(Some(length)) if buffer.len() >= length => match Self::parse_with_len(buffer) {
+                Ok((message, length)) => ParseState::Complete(message, length),
+                Err(e) => ParseState::Error(e),
+            },
+            Ok(_) => ParseState::Partial,
+            Err(kind) => ParseState::Error(Error::Malformed(kind)),
+        }
+    }
+
+    /// Parses a request without copying out of `buffer`, for callers that only need a look at it.
+    pub fn parse_ref(buffer: &[u8]) -> Result<RequestRef<'_>> {
+        let request: Parsed<RequestRef> = try {
+            let end = find_bytes(buffer, b"\r\n\r\n").ok_or(MalformedKind::Incomplete)?;
+
+            let head = RawHead::scan(&buffer[..end])?;
+
+            let (method, target, version) = Self::parse_request_line(head.start)?;
+
+            Self::check_framing(Some(version), &head.fields)?;
+
+            let body = &buffer[end + 4..];
+
+            let body = match head.framing()? {
+                Some(Framing::Chunked { .. }) => {
+                    let chunked = Self::parse_chunked(body)?;
+
+                    let (payload, _, _) = chunked.ok_or(MalformedKind::Incomplete)?;
+
+                    borrow::Cow::Owned(payload)
+                }
+                Some(Framing::Length(length)) => {
+                    borrow::Cow::Borrowed(body.get(..length).ok_or(MalformedKind::Incomplete)?)
+                }
+                None => borrow::Cow::Borrowed(&body[..0]),
+            };
+
+            RequestRef { method, target, version, headers: head.fields, body }
+        };
+
+        request.map_err(|kind| Self::head_error(buffer, kind))
+    }
+
+    pub fn read_head<R: io::Read>(reader: R) -> Result<(Self, BodyReader<R>)> {
+        Self::read_head_within(reader, DEFAULT_MAX_HEADER_BYTES)
+    }
+
+    pub fn read_head_within<R>(reader: R, max_header_bytes: usize) -> Result<(Self, BodyReader<R>)>
+    where
+        R: io::Read,
+    {
+        Self::read_head_buffered(reader, vec![], max_header_bytes)
+    }
+
+    fn read_head_buffered<R>(
+        mut reader: R,
+        mut buffer: Vec<u8>,
+        max_header_bytes: usize,
+    ) -> Result<(Self, BodyReader<R>)>
+    where
+        R: io::Read,
+    {
+        let mut octets = [0; READ_BUFFER_BYTES];
+
+        let head = loop {
+            if let Some(head) = find_bytes(&buffer, b"\r\n\r\n") {
+                if head + 4 > max_header_bytes {
+                    return Err(Error::Malformed(MalformedKind::HeaderTooLarge));
+                }
+
+                break head;
+            }
+
+            if buffer.len() > max_header_bytes {
+                return Err(Error::Malformed(MalformedKind::HeaderTooLarge));
+            }
+
+            let length = match reader.read(&mut octets) {
+                Ok(0) if buffer.is_empty() => Err(io::Error::from(io::ErrorKind::UnexpectedEof))?,
+                Ok(0) => return Err(Error::Malformed(MalformedKind::Incomplete)),
+                Ok(length) => length,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e)?,
+            };
+
+            buffer.extend_from_slice(&octets[..length]);
+        };
+
+        let parsed: Parsed<_> = try {
+            let raw = RawHead::scan(&buffer[..head])?;
+
+            (Self::parse_raw_head(&raw)?, raw.framing()?)
+        };
+
+        let ((start, headers), framing) =
+            parsed.map_err(|kind| Self::head_error(&buffer[..head], kind))?;
+
+        let framing = framing.unwrap_or(Framing::Length(0));
+
+        buffer.drain(..head + 4);
+
+        let message = start.into_message(vec![Frame::Headers { headers }]);
+
+        let body = BodyReader {
+            reader,
+            buffer,
+            framing,
+            limit: usize::MAX,
+            exceeded: false,
+            max_header_bytes,
+        };
+
+        Ok((message, body))
+    }
+
+    fn parse_head(head: &[u8]) -> Parsed<(StartLine, Headers)> {
+        Self::parse_raw_head(&RawHead::scan(head)?)
+    }
+
+    fn parse_raw_head(head: &RawHead) -> Parsed<(StartLine, Headers)> {
+        let start = if head.start.starts_with("HTTP/") {
+            let (version, code, _) = Self::parse_response_line(head.start)?;
+
+            StartLine::Response { version, code }
+        } else {
+            let (method, target, version) = Self::parse_status_line(head.start)?;
+
+            StartLine::Request { method, target, version }
+        };
+
+        let request = match &start {
+            StartLine::Request { version, .. } => Some(*version),
+            StartLine::Response { .. } => None,
+        };
+
+        Self::check_framing(request, &head.fields)?;
+
+        let mut headers = Headers::new();
+
+        for (name, value) in &head.fields {
+            headers.append(Header { name: name.to_string(), value: value.to_string() });
+        }
+
+        Ok((start, headers))
+    }
+
+    /// Picks the error for a head that failed to parse: a request line naming a well-formed but
+    /// unknown version is unsupported rather than malformed.
+    fn head_error(head: &[u8], kind: MalformedKind) -> Error {
+        let line = head.split(|&octet| octet == b'\r').next().unwrap_or_default();
+
+        let version = str::from_utf8(line).ok().and_then(|line| line.splitn(3, ' ').nth(2));
+
+        match version {
+            Some(version)
+                if kind == MalformedKind::Version
+                    && Self::parse_version_number(version).is_some() =>
+            {
+                Error::UnsupportedVersion
+            }
+            _ => Error::Malformed(kind),
+        }
+    }
+
+    /// Refuses the header combinations that let two parsers disagree on where a message ends.
+    fn check_framing(request: Option<Version>, fields: &[(&str, &str)]) -> Parsed<()> {
+        let values = |name: &'static str| {
+            fields
+                .iter()
+                .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.trim())
+        };
+
+        if let Some(version) = request {
+            if version.major == 1 && version.minor >= 1 && values("Host").count() != 1 {
+                Err(MalformedKind::Host)?
+            }
+
+            let encoded = values("Transfer-Encoding").next().is_some();
+
+            if encoded && values("Content-Length").next().is_some() {
+                Err(MalformedKind::Framing)?
+            }
+        }
+
+        let mut lengths = values("Content-Length");
+
+        let first = lengths.next();
+
+        if !lengths.all(|length| Some(length) == first) {
+            Err(MalformedKind::ContentLength)?
+        }
+
+        Ok(())
+    }
+
+    fn is_preface(&self) -> bool {
+        match self {
+            Message::Request { method: Method::Extension(method), target, version, .. } => {
+                method == "PRI" && target == "*" && *version == Version::HTTP_2
+            }
+            _ => false,
+        }
+    }
+
+    /// Works out how long the message at the start of `buffer` is, or `None` while more is needed.
+    fn expected_len(buffer: &[u8]) -> Parsed<Option<usize>> {
+        let Some(end) = find_bytes(buffer, b"\r\n\r\n") else {
+            return Ok(None);
+        };
+
+        let head = RawHead::scan(&buffer[..end])?;
+
+        let body = &buffer[end + 4..];
+
+        let length = match head.framing()? {
+            // Only the length is wanted here, so walk the chunks without copying them.
+            Some(Framing::Chunked { .. }) => Self::scan_chunked(body, |_| {})?.map(|(_, end)| end),
+            Some(Framing::Length(length)) => Some(length),
+            None => Some(0),
+        };
+
+        Ok(length.map(|length| end + 4 + length))
+    }
+
+    /// Reads a Content-Length, which must be `1*DIGIT`; `parse` alone would also take a sign.
+    fn parse_length(length: &str) -> Parsed<usize> {
+        let length = length.trim();
+
+        if length.is_empty() || !length.bytes().all(|b| b.is_ascii_digit()) {
+            Err(MalformedKind::ContentLength)?
+        }
+
+        length.parse().map_err(|_| MalformedKind::ContentLength)
+    }
+
+    fn parse_chunked(data: &[u8]) -> Parsed<Option<(Vec<u8>, Headers, usize)>> {
+        let mut payload = vec![];
+
+        let scanned = Self::scan_chunked(data, |chunk| payload.extend_from_slice(chunk))?;
+
+        let Some((start, length)) = scanned else {
+            return Ok(None);
+        };
+
+        let trailers = str::from_utf8(&data[start..length - 2]).ok().and_then(Self::parse_headers);
+
+        Ok(Some((payload, trailers.ok_or(MalformedKind::Chunk)?, length)))
+    }
+
+    /// Walks a chunked body, handing each chunk to `chunk`, and returns where its trailers start
+    /// with the encoded length; `Ok(None)` means the body is cut short rather than malformed.
+    fn scan_chunked(data: &[u8], mut chunk: impl FnMut(&[u8])) -> Parsed<Option<(usize, usize)>> {
+        let mut cursor = 0;
+
+        loop {
+            let Some(line) = find_bytes(&data[cursor..], b"\r\n") else {
+                return Ok(None);
+            };
+
+            let size = &data[cursor..cursor + line];
+
+            let size = str::from_utf8(size).map_err(|_| MalformedKind::Chunk)?;
+
+            let size = size.split(';').next().unwrap_or_default().trim();
+
+            let size = usize::from_str_radix(size, 16).map_err(|_| MalformedKind::Chunk)?;
+
+            cursor += line + 2;
+
+            if size == 0 {
+                break;
+            }
+
+            let end = cursor.checked_add(size).ok_or(MalformedKind::Chunk)?;
+
+            let Some(payload) = data.get(cursor..end) else {
+                return Ok(None);
+            };
+
+            chunk(payload);
+
+            cursor = end;
+
+            match data.get(cursor..cursor + 2) {
+                Some(b"\r\n") => cursor += 2,
+                Some(_) => Err(MalformedKind::Chunk)?,
+                None => return Ok(None),
+            }
+        }
+
+        let start = cursor;
+
+        loop {
+            let Some(line) = find_bytes(&data[cursor..], b"\r\n") else {
+                return Ok(None);
+            };
+
+            cursor += line + 2;
+
+            if line == 0 {
+                break;
+            }
+        }
+
+        Ok(Some((start, cursor)))
+    }
+
+    fn parse_status_line(info: &'_ str) -> Parsed<(Method, Target, Version)> {
+        let (method, target, version) = Self::parse_request_line(info)?;
+
+        let method = method.try_into().map_err(|_| MalformedKind::Method)?;
+
+        Ok((method, target.to_string(), version))
+    }
+
+    fn parse_request_line(info: &str) -> Parsed<(&str, &str, Version)> {
+        let mut parts = info.splitn(3, ' ');
+
+        let (Some(method), Some(target), Some(version)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            Err(MalformedKind::StartLine)?
+        };
+
+        if !is_token(method) {
+            Err(MalformedKind::Method)?
+        }
+
+        if target.is_empty() || target.bytes().any(|b| b == b' ' || b.is_ascii_control()) {
+            Err(MalformedKind::Target)?
+        }
+
+        let version = Self::parse_version(version).ok_or(MalformedKind::Version)?;
+
+        Ok((method, target, version))
+    }
+
+    fn parse_response_line(info: &'_ str) -> Parsed<(Version, Code, Status)> {
+        let mut data = info.splitn(3, ' ');
+
+        let version = data.next().and_then(Self::parse_version).ok_or(MalformedKind::Version)?;
+
+        let code = data.next().ok_or(MalformedKind::StartLine)?;
+
+        let code = code.parse::<u16>().ok().and_then(|code| code.try_into().ok());
+
+        let status = data.next().unwrap_or_default().to_string();
+
+        Ok((version, code.ok_or(MalformedKind::Status)?, status))
+    }
+
+    fn parse_version(info: &'_ str) -> Option<Version> {
+        let version = Self::parse_version_number(info)?;
+
+        match version {
+            Version::HTTP_09 | Version::HTTP_10 | Version::HTTP_11 | Version::HTTP_2 => {
+                Some(version)
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_version_number(info: &'_ str) -> Option<Version> {
+        let (major, minor) = info.strip_prefix("HTTP/")?.split_once('.')?;
+
+        let digit = |data: &'_ str| -> Option<u8> {
+            match data.as_bytes() {
+                [digit @ b'0'..=b'9'] => Some(digit - b'0'),
+                _ => None,
+            }
+        };
+
+        Some(Version { major: digit(major)?, minor: digit(minor)? })
+    }
+
+    fn parse_headers(info: &'_ str) -> Option<Headers> {
+        let mut headers = Headers::new();
+
+        for (name, value) in Self::parse_fields(info).ok()? {
+            headers.append(Header { name: name.to_string(), value: value.to_string() });
+        }
+
+        Some(headers)
+    }
+
+    fn parse_fields(info: &str) -> Parsed<Vec<(&str, &str)>> {
+        let mut fields = vec![];
+
+        for line in info.lines() {
+            // Obsolete line folding (RFC 7230 section 3.2.4) is a smuggling vector, so refuse it.
+            if line.starts_with([' ', '\t']) {
+                Err(MalformedKind::Header)?
+            }
+
+            let (name, value) = line.split_once(':').ok_or(MalformedKind::Header)?;
+
+            let value = value.trim_start();
+
+            if !is_token(name) {
+                Err(MalformedKind::Header)?
+            }
+
+            if value.bytes().any(|b| b.is_ascii_control() && b != b'\t') {
+                Err(MalformedKind::Value)?
+            }
+
+            fields.push((name, value));
+        }
+
+        Ok(fields)
+    }
+}
+
+pub struct MessageBuilder {
+    version: Version,
+    code: Code,
+    headers: Headers,
+    payload: Vec<u8>,
+    reader: Option<Box<dyn io::Read + Send>>,
+    trailers: Headers,
+}
+
+impl Default for MessageBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MessageBuilder {
+    pub fn new() -> Self {
+        Self {
+            version: Version::HTTP_11,
+            code: Code::Success,
+            headers: Headers::new(),
+            payload: vec![],
+            reader: None,
+            trailers: Headers::new(),
+        }
+    }
+
+    pub fn version(self, version: Version) -> Self {
+        Self { version, ..self }
+    }
+
+    pub fn code(self, code: Code) -> Self {
+        Self { code, ..self }
+    }
+
+    pub fn header(self, header: Header) -> Self {  
+        let Self {
+            mut headers,
+            ..
+        } = self;
+        
+        headers.append(header);
+
+        Self { headers, ..self }
+    }
+
+    pub fn body(self, body: impl AsRef<[u8]>) -> Self {
+        let Self {
+            mut payload,
+            ..
+        } = self;
+        
+        payload.extend_from_slice(body.as_ref());
+
+        Self { payload, ..self }
+    }
+
+    /// Streams the body from `reader` after any bytes given to `body`, sent chunked unless a
+    /// Content-Length header is set.
+    pub fn body_stream(self, reader: impl io::Read + Send + 'static) -> Self {
+        Self { reader: Some(Box::new(reader)), ..self }
+    }
+
+    /// Adds a trailer field sent after the last chunk, which makes the body chunked.
+    pub fn trailer(self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        let Self {
+            mut trailers,
+            ..
+        } = self;
+
+        trailers.append(Header { name: name.into(), value: value.into() });
+
+        Self { trailers, ..self }
+    }
+
+    pub fn build(self) -> Message {
+        let MessageBuilder {
+            version,
+            code,
+            mut headers,
+            payload,
+            reader,
+            trailers,
+        } = self;
+
+        let bodiless = matches!(u16::from(code), 100..=199 | 204 | 304);
+
+        let payload = if bodiless { vec![] } else { payload };
+
+        let streamed = reader.is_some() && !headers.contains("Content-Length");
+
+        if !bodiless && (streamed || !trailers.is_empty()) {
+            headers.remove("Content-Length");
+
+            headers.insert("Transfer-Encoding", "chunked");
+        }
+
+        if !bodiless && !trailers.is_empty() {
+            let names = trailers.iter().map(|trailer| trailer.name.as_str()).collect::<Vec<_>>();
+
+            headers.insert("Trailer", names.join(", "));
+        }
+
+        if !bodiless && reader.is_none() && !headers.contains("Transfer-Encoding") {
+            let length = payload.len().to_string();
+
+            if let Some(declared) = headers.get("Content-Length") {
+                debug_assert_eq!(declared, length, "Content-Length does not match the payload");
+            }
+
+            headers.insert("Content-Length", length);
+        }
+
+        let mut frames = vec![];
+
+        let headers = Frame::Headers { headers };
+
+        frames.push(headers);
+
+        let data = Frame::Data { payload };
+
+        frames.push(data);
+
+        if let Some(reader) = reader.filter(|_| !bodiless) {
+            frames.push(Frame::Reader { reader });
+        }
+
+        if !bodiless && !trailers.is_empty() {
+            frames.push(Frame::Trailers { headers: trailers });
+        }
+
+        Message::Response {
+            version,
+            code,
+            frames,
+        }
+    }
+}
+
+pub struct RequestBuilder {
+    method: Method,
+    target: Target,
+    version: Version,
+    headers: Headers,
+    payload: Vec<u8>,
+}
+
+impl RequestBuilder {
+    pub fn new(method: Method, target: impl Into<Target>) -> Self {
+        Self {
+            method,
+            target: target.into(),
+            version: Version::HTTP_11,
+            headers: Headers::new(),
+            payload: vec![],
+        }
+    }
+
+    pub fn version(self, version: Version) -> Self {
+        Self { version, ..self }
+    }
+
+    pub fn header(self, header: Header) -> Self {
+        let Self {
+            mut headers,
+            ..
+        } = self;
+
+        headers.append(header);
+
+        Self { headers, ..self }
+    }
+
+    pub fn body(self, body: impl AsRef<[u8]>) -> Self {
+        let Self {
+            mut payload,
+            ..
+        } = self;
+
+        payload.extend_from_slice(body.as_ref());
+
+        Self { payload, ..self }
+    }
+
+    pub fn build(self) -> Message {
+        let RequestBuilder {
+            method,
+            target,
+            version,
+            headers,
+            payload,
+        } = self;
+
+        let mut frames = vec![];
+
+        let headers = Frame::Headers { headers };
+
+        frames.push(headers);
+
+        let data = Frame::Data { payload };
+
+        frames.push(data);
+
+        Message::Request {
+            method,
+            target,
+            version,
+            frames,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+/// A header field whose name keeps the casing it was sent with, while lookups ignore case.
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+/// Header fields kept in the order they were parsed or added, which serialization preserves.
+#[derive(Default)]
+pub struct Headers {
+    headers: Vec<Header>,
+}
+
+impl Headers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).into_iter().next()
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+            .collect()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.headers.iter().any(|header| header.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Replaces the first field called `name` in place, keeping its casing, or appends one.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        let value = value.into();
+
+        let position = self
+            .headers
+            .iter()
+            .position(|header| header.name.eq_ignore_ascii_case(&name));
+
+        let Some(index) = position else {
+            self.headers.push(Header { name, value });
+            return;
+        };
+
+        self.headers[index].value = value;
+    }
+
+    pub fn append(&mut self, header: Header) {
+        self.headers.push(header);
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+
+        self.headers.retain(|header| {
+            let matches = header.name.eq_ignore_ascii_case(name);
+
+            if matches && removed.is_none() {
+                removed = Some(header.value.clone());
+            }
+
+            !matches
+        });
+
+        removed
+    }
+
+    pub fn len(&self) -> usize {
+        self.headers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Header> {
+        self.headers.iter()
+    }
+}
+
+impl IntoIterator for Headers {
+    type Item = Header;
+    type IntoIter = vec::IntoIter<Header>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.headers.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Headers {
+    type Item = &'a Header;
+    type IntoIter = slice::Iter<'a, Header>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.headers.iter()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SameSite::Strict => write!(f, "Strict"),
+            SameSite::Lax => write!(f, "Lax"),
+            SameSite::None => write!(f, "None"),
+        }
+    }
+}
+
+pub struct Cookie {
+    name: String,
+    value: String,
+    expires: Option<time::SystemTime>,
+    max_age: Option<u64>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            value: value.into(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    pub fn expires(self, expires: time::SystemTime) -> Self {
+        Self { expires: Some(expires), ..self }
+    }
+
+    pub fn max_age(self, seconds: u64) -> Self {
+        Self { max_age: Some(seconds), ..self }
+    }
+
+    pub fn domain(self, domain: impl Into<String>) -> Self {
+        Self { domain: Some(domain.into()), ..self }
+    }
+
+    pub fn path(self, path: impl Into<String>) -> Self {
+        Self { path: Some(path.into()), ..self }
+    }
+
+    pub fn secure(self) -> Self {
+        Self { secure: true, ..self }
+    }
+
+    pub fn http_only(self) -> Self {
+        Self { http_only: true, ..self }
+    }
+
+    pub fn same_site(self, same_site: SameSite) -> Self {
+        Self { same_site: Some(same_site), ..self }
+    }
+}
+
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+
+        if let Some(expires) = self.expires {
+            write!(f, "; Expires={}", http_date(expires))?;
+        }
+
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age)?;
+        }
+
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+
+        if self.secure {
+            write!(f, "; Secure")?;
+        }
+
+        if self.http_only {
+            write!(f, "; HttpOnly")?;
+        }
+
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Cookie> for Header {
+    fn from(cookie: Cookie) -> Self {
+        Header { name: "Set-Cookie".into(), value: cookie.to_string() }
+    }
+}
+
+pub enum Frame {
+    Headers { headers: Headers },
+    Data { payload: Vec<u8> },
+    Reader { reader: Box<dyn io::Read + Send> },
+    Trailers { headers: Headers },
+}
+
+struct Encoded {
+    bytes: Vec<u8>,
+    reader: Option<Box<dyn io::Read + Send>>,
+    chunked: bool,
+    trailers: Headers,
+}
+
+impl Encoded {
+    fn write_to<W: io::Write>(self, writer: &mut W) -> io::Result<usize> {
+        writer.write_all(&self.bytes)?;
+
+        let mut written = self.bytes.len();
+
+        if let Some(mut reader) = self.reader {
+            let copied = if self.chunked {
+                io::copy(&mut reader, &mut Chunked { writer: &mut *writer })?
+            } else {
+                io::copy(&mut reader, writer)?
+            };
+
+            written += copied as usize;
+        }
+
+        if self.chunked {
+            let mut last = String::from("0\r\n");
+
+            for trailer in self.trailers {
+                last += &format!("{}: {}\r\n", trailer.name, trailer.value);
+            }
+
+            last += "\r\n";
+
+            writer.write_all(last.as_bytes())?;
+
+            written += last.len();
+        }
+
+        writer.flush()?;
+
+        Ok(written)
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut bytes = vec![];
+
+        self.write_to(&mut bytes).ok();
+
+        bytes
+    }
+}
+
+/// Frames everything written to it as one HTTP/1.1 chunk per write.
+struct Chunked<W> {
+    writer: W,
+}
+
+impl<W: io::Write> io::Write for Chunked<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if data.is_empty() {
+            return Ok(0);
+        }
+
+        write!(self.writer, "{:X}\r\n", data.len())?;
+
+        self.writer.write_all(data)?;
+
+        self.writer.write_all(b"\r\n")?;
+
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+type Handler = Box<dyn Fn(&Message, &Params) -> Message + Send + Sync>;
+type RequestHook = sync::Arc<dyn Fn(&RequestInfo) + Send + Sync>;
+type ResponseHook = sync::Arc<dyn Fn(&ResponseInfo) + Send + Sync>;
+type Layer = sync::Arc<dyn Middleware>;
+
+#[derive(Default)]
+pub struct Params {
+    params: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.params.iter().map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+}
+
+struct Route {
+    method: Method,
+    path: String,
+    handler: Handler,
+}
+
+impl Route {
+    fn matches(&self, path: &'_ str) -> Option<Params> {
+        let mut pattern = self.path.split('/');
+        let mut segments = path.split('/');
+
+        let mut params = vec![];
+
+        loop {
+            match (pattern.next(), segments.next()) {
+                (None, None) => break,
+                (Some(expected), Some(segment)) => {
+                    if let Some(name) = expected.strip_prefix(':') {
+                        if segment.is_empty() {
+                            None?
+                        }
+
+                        params.push((name.to_string(), segment.to_string()));
+                    } else if expected != segment {
+                        None?
+                    }
+                }
+                _ => None?,
+            }
+        }
+
+        Some(Params { params })
+    }
+
+    fn specificity(&self) -> Vec<bool> {
+        self.path.split('/').map(|segment| !segment.starts_with(':')).collect()
+    }
+}
+
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn route<F>(self, method: Method, path: &str, handler: F) -> Self
+    where
+        F: Fn(&Message, &Params) -> Message + Send + Sync + 'static,
+    {
+        let Self { mut routes } = self;
+
+        routes.push(Route {
+            method,
+            path: Self::normalize(path).to_string(),
+            handler: Box::new(handler),
+        });
+
+        Self { routes }
+    }
+
+    pub fn get<F>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&Message, &Params) -> Message + Send + Sync + 'static,
+    {
+        self.route(Method::Get, path, handler)
+    }
+
+    pub fn post<F>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&Message, &Params) -> Message + Send + Sync + 'static,
+    {
+        self.route(Method::Post, path, handler)
+    }
+
+    pub fn put<F>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&Message, &Params) -> Message + Send + Sync + 'static,
+    {
+        self.route(Method::Put, path, handler)
+    }
+
+    pub fn delete<F>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&Message, &Params) -> Message + Send + Sync + 'static,
+    {
+        self.route(Method::Delete, path, handler)
+    }
+
+    pub fn patch<F>(self, path: &str, handler: F) -> Self
+    where
+        F: Fn(&Message, &Params) -> Message + Send + Sync + 'static,
+    {
+        self.route(Method::Patch, path, handler)
+    }
+
+    pub fn handle(&self, request: &Message) -> Message {
+        let (Some(method), Ok(path)) = (request.method(), request.decoded_path()) else {
+            return Message::error(Code::BadRequest);
+        };
+
+        if matches!(method, Method::Options) && path == "*" {
+            return Self::options(self.routes.iter());
+        }
+
+        let path = Self::normalize(&path);
+
+        let routes = self
+            .routes
+            .iter()
+            .filter_map(|route| Some((route, route.matches(path)?)))
+            .collect::<Vec<_>>();
+
+        if routes.is_empty() {
+            return Message::error(Code::NotFound);
+        }
+
+        let best = |method: &Method| {
+            routes
+                .iter()
+                .filter(|(route, _)| route.method == *method)
+                .max_by_key(|(route, _)| route.specificity())
+        };
+
+        let best = match method {
+            Method::Head => best(method).or_else(|| best(&Method::Get)),
+            _ => best(method),
+        };
+
+        if let Some((route, params)) = best {
+            return (route.handler)(request, params);
+        }
+
+        if matches!(method, Method::Options) {
+            return Self::options(routes.iter().map(|(route, _)| *route));
+        }
+
+        let allow = Self::allow(routes.iter().map(|(route, _)| *route));
+
+        let mut message = Message::error(Code::MethodNotAllowed);
+
+        message.push_header(Header { name: "Allow".into(), value: allow });
+
+        message
+    }
+
+    fn options<'a>(routes: impl Iterator<Item = &'a Route>) -> Message {
+        let allow = Self::allow(routes);
+
+        MessageBuilder::new()
+            .code(Code::NoContent)
+            .header(Header { name: "Allow".into(), value: allow })
+            .build()
+    }
+
+    /// Lists the routed methods plus HEAD for GET routes and OPTIONS, answered for every path.
+    fn allow<'a>(routes: impl Iterator<Item = &'a Route>) -> String {
+        let mut methods = Vec::<&Method>::new();
+
+        for route in routes {
+            if !methods.contains(&&route.method) {
+                methods.push(&route.method);
+            }
+        }
+
+        if methods.contains(&&Method::Get) && !methods.contains(&&Method::Head) {
+            methods.push(&Method::Head);
+        }
+
+        if !methods.contains(&&Method::Options) {
+            methods.push(&Method::Options);
+        }
+
+        methods.iter().map(|method| method.to_string()).collect::<Vec<_>>().join(", ")
+    }
+
+    fn normalize(path: &'_ str) -> &'_ str {
+        match path.trim_end_matches('/') {
+            "" => "/",
+            path => path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<u64>,
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows requests from `origin`, or from anywhere when given `*`. Origins only the wildcard
+    /// admits are answered with a literal `*` and never with credentials.
+    pub fn origin(mut self, origin: impl Into<String>) -> Self {
+        self.origins.push(origin.into());
+
+        self
+    }
+
+    pub fn method(mut self, method: Method) -> Self {
+        self.methods.push(method);
+
+        self
+    }
+
+    pub fn header(mut self, name: impl Into<String>) -> Self {
+        self.headers.push(name.into());
+
+        self
+    }
+
+    pub fn credentials(self, credentials: bool) -> Self {
+        Self { credentials, ..self }
+    }
+
+    pub fn max_age(self, seconds: u64) -> Self {
+        Self { max_age: Some(seconds), ..self }
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        self.allow_origin(origin).is_some()
+    }
+
+    /// Picks the Allow-Origin value: the origin itself when listed, otherwise `*` if allowed.
+    fn allow_origin<'a>(&self, origin: &'a str) -> Option<&'a str> {
+        if self.origins.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)) {
+            Some(origin)
+        } else if self.origins.iter().any(|allowed| allowed == "*") {
+            Some("*")
+        } else {
+            None
+        }
+    }
+
+    fn preflight(&self, origin: &str, requested: &str) -> Message {
+        let mut response = MessageBuilder::new().code(Code::NoContent);
+
+        if !self.allows(origin) {
+            return response.build();
+        }
+
+        let methods = if self.methods.is_empty() {
+            requested.to_string()
+        } else {
+            self.methods.iter().map(|method| method.to_string()).collect::<Vec<_>>().join(", ")
+        };
+
+        headers! { response, "Access-Control-Allow-Methods": methods };
+
+        if !self.headers.is_empty() {
+            headers! { response, "Access-Control-Allow-Headers": self.headers.join(", ") };
+        }
+
+        if let Some(max_age) = self.max_age {
+            headers! { response, "Access-Control-Max-Age": max_age };
+        }
+
+        response.build()
+    }
+
+    fn apply(&self, origin: &str, response: &mut Message) {
+        response.push_header(Header { name: "Vary".into(), value: "Origin".into() });
+
+        if response.headers().contains("Access-Control-Allow-Origin") {
+            return;
+        }
+
+        let Some(allowed) = self.allow_origin(origin) else {
+            return;
+        };
+
+        let allow = Header { name: "Access-Control-Allow-Origin".into(), value: allowed.into() };
+
+        response.push_header(allow);
+
+        // Echoing every origin with credentials would let any site read credentialed responses.
+        if self.credentials && allowed != "*" {
+            let credentials = "Access-Control-Allow-Credentials";
+
+            response.push_header(Header { name: credentials.into(), value: "true".into() });
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    pub method: Method,
+    pub path: String,
+    pub bytes: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResponseInfo {
+    pub method: Method,
+    pub path: String,
+    pub code: Code,
+    pub bytes: usize,
+    pub elapsed: time::Duration,
+    /// Why the response could not be written, such as `BrokenPipe` when the client left early.
+    pub error: Option<io::ErrorKind>,
+}
+
+/// Wraps the handler, seeing each request before it and each response after it via `next`.
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: &Message, next: &dyn Fn(&Message) -> Message) -> Message;
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(&Message, &dyn Fn(&Message) -> Message) -> Message + Send + Sync,
+{
+    fn handle(&self, request: &Message, next: &dyn Fn(&Message) -> Message) -> Message {
+        self(request, next)
+    }
+}
+
+#[derive(Clone)]
+struct Config {
+    read_timeout: Option<time::Duration>,
+    write_timeout: Option<time::Duration>,
+    continue_timeout: time::Duration,
+    max_header_bytes: usize,
+    max_body_bytes: usize,
+    on_request: Option<RequestHook>,
+    on_response: Option<ResponseHook>,
+    #[cfg(feature = "gzip")]
+    compress: bool,
+    #[cfg(feature = "tls")]
+    tls: Option<sync::Arc<rustls::ServerConfig>>,
+    method_override: bool,
+    strict: bool,
+    trace: bool,
+    server_name: Option<String>,
+    cors: Option<Cors>,
+    middleware: Vec<Layer>,
+    buffers: BufferPool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            read_timeout: Some(DEFAULT_TIMEOUT),
+            write_timeout: Some(DEFAULT_TIMEOUT),
+            continue_timeout: DEFAULT_CONTINUE_TIMEOUT,
+            max_header_bytes: DEFAULT_MAX_HEADER_BYTES,
+            max_body_bytes: DEFAULT_MAX_BODY_BYTES,
+            on_request: None,
+            on_response: None,
+            #[cfg(feature = "gzip")]
+            compress: false,
+            #[cfg(feature = "tls")]
+            tls: None,
+            method_override: false,
+            strict: false,
+            trace: false,
+            server_name: Some(DEFAULT_SERVER_NAME.into()),
+            cors: None,
+            middleware: vec![],
+            buffers: BufferPool::default(),
+        }
+    }
+}
+
+/// Read buffers kept across connections so each one does not start from an empty allocation.
+#[derive(Clone, Default)]
+struct BufferPool {
+    buffers: sync::Arc<sync::Mutex<Vec<Vec<u8>>>>,
+}
+
+impl BufferPool {
+    fn take(&self) -> Vec<u8> {
+        self.buffers.lock().ok().and_then(|mut buffers| buffers.pop()).unwrap_or_default()
+    }
+
+    fn give(&self, mut buffer: Vec<u8>) {
+        if buffer.capacity() > MAX_POOLED_BYTES {
+            return;
+        }
+
+        buffer.clear();
+
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < MAX_POOLED_BUFFERS {
+                buffers.push(buffer);
+            }
+        }
+    }
+}
+
+pub struct Server {
+    listeners: Vec<net::TcpListener>,
+    workers: usize,
+    config: Config,
+    addrs: Vec<net::SocketAddr>,
+    shutdown: sync::Arc<atomic::AtomicBool>,
+}
+
+#[derive(Clone)]
+pub struct ShutdownHandle {
+    flag: sync::Arc<atomic::AtomicBool>,
+    addrs: Vec<net::SocketAddr>,
+}
+
+impl ShutdownHandle {
+    pub fn shutdown(&self) {
+        self.flag.store(true, atomic::Ordering::SeqCst);
+
+        for &(mut addr) in &self.addrs {
+            if addr.ip().is_unspecified() {
+                match addr {
+                    net::SocketAddr::V4(_) => addr.set_ip(net::Ipv4Addr::LOCALHOST.into()),
+                    net::SocketAddr::V6(_) => addr.set_ip(net::Ipv6Addr::LOCALHOST.into()),
+                }
+            }
+
+            // Wake each accept loop so that it notices the flag.
+            net::TcpStream::connect(addr).ok();
+        }
+    }
+}
+
+impl Server {
+    pub fn bind(port: u16) -> Result<Self> {
+        Self::bind_addr((LOCAL_HOST, port))
+    }
+
+    pub fn bind_addr(addr: impl net::ToSocketAddrs) -> Result<Self> {
+        Self::from_listeners(vec![Self::listen(addr)?])
+    }
+
+    /// Binds every address and serves connections from all of them with the same handler.
+    pub fn bind_all(addrs: &[net::SocketAddr]) -> Result<Self> {
+        let listeners = addrs.iter().map(Self::listen).collect::<Result<Vec<_>>>()?;
+
+        if listeners.is_empty() {
+            Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to bind"))?
+        }
+
+        Self::from_listeners(listeners)
+    }
+
+    fn listen(addr: impl net::ToSocketAddrs) -> Result<net::TcpListener> {
+        let mut failure = None;
+
+        let listener = addr.to_socket_addrs()?.find_map(|addr| {
+            net::TcpListener::bind(addr).map_err(|err| failure = Some((addr, err))).ok()
+        });
+
+        let listener = match (listener, failure) {
+            (Some(listener), _) => listener,
+            (None, Some((addr, err))) if err.kind() == io::ErrorKind::AddrInUse => {
+                Err(Error::AddrInUse(addr, err))?
+            }
+            (None, Some((_, err))) => Err(err)?,
+            (None, None) => Err(io::Error::new(io::ErrorKind::InvalidInput, "no address to bind"))?,
+        };
+
+        Ok(listener)
+    }
+
+    fn from_listeners(listeners: Vec<net::TcpListener>) -> Result<Self> {
+        let addrs = listeners
+            .iter()
+            .map(net::TcpListener::local_addr)
+            .collect::<io::Result<Vec<_>>>()?;
+
+        Ok(Self {
+            listeners,
+            workers: DEFAULT_WORKERS,
+            config: Config::default(),
+            addrs,
+            shutdown: sync::Arc::new(atomic::AtomicBool::new(false)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<net::SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    /// Returns the address of every listener, in the order they were bound.
+    pub fn local_addrs(&self) -> &[net::SocketAddr] {
+        &self.addrs
+    }
+
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        ShutdownHandle { flag: self.shutdown.clone(), addrs: self.addrs.clone() }
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn bind_tls(
+        addr: impl net::ToSocketAddrs,
+        cert: impl AsRef<path::Path>,
+        key: impl AsRef<path::Path>,
+    ) -> Result<Self> {
+        use rustls::pki_types::pem::PemObject;
+        use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+
+        let (cert, key) = (cert.as_ref(), key.as_ref());
+
+        let certs = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<result::Result<Vec<_>, _>>())
+            .map_err(|e| {
+                Error::Tls(format!("failed to load certificate {}: {}", cert.display(), e))
+            })?;
+
+        if certs.is_empty() {
+            Err(Error::Tls(format!("no certificate found in {}", cert.display())))?
+        }
+
+        let key = PrivateKeyDer::from_pem_file(key).map_err(|e| {
+            Error::Tls(format!("failed to load private key {}: {}", key.display(), e))
+        })?;
+
+        let provider = sync::Arc::new(rustls::crypto::ring::default_provider());
+
+        let mut tls = rustls::ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(certs, key))
+            .map_err(|e| Error::Tls(format!("invalid certificate or private key: {}", e)))?;
+
+        tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let mut server = Self::bind_addr(addr)?;
+
+        server.config.tls = Some(sync::Arc::new(tls));
+
+        Ok(server)
+    }
+
+    pub fn with_workers(self, workers: usize) -> Self {
+        Self { workers: workers.max(1), ..self }
+    }
+
+    /// Sets the read timeout applied to each accepted connection, 30 seconds by default.
+    pub fn set_read_timeout(&mut self, timeout: Option<time::Duration>) {
+        self.config.read_timeout = timeout;
+    }
+
+    /// Sets the write timeout applied to each accepted connection, 30 seconds by default.
+    pub fn set_write_timeout(&mut self, timeout: Option<time::Duration>) {
+        self.config.write_timeout = timeout;
+    }
+
+    /// Sets how long a client may take to send its body after 100 Continue, 10 seconds by default.
+    pub fn set_continue_timeout(&mut self, timeout: time::Duration) {
+        self.config.continue_timeout = timeout;
+    }
+
+    /// Sets the largest request head accepted before answering 431, 8 KiB by default.
+    pub fn set_max_header_bytes(&mut self, max_header_bytes: usize) {
+        self.config.max_header_bytes = max_header_bytes;
+    }
+
+    /// Sets the largest request body accepted before answering 413, 4 MiB by default.
+    pub fn set_max_body_bytes(&mut self, max_body_bytes: usize) {
+        self.config.max_body_bytes = max_body_bytes;
+    }
+
+    pub fn on_request<F: Fn(&RequestInfo) + Send + Sync + 'static>(&mut self, hook: F) {
+        self.config.on_request = Some(sync::Arc::new(hook));
+    }
+
+    pub fn on_response<F: Fn(&ResponseInfo) + Send + Sync + 'static>(&mut self, hook: F) {
+        self.config.on_response = Some(sync::Arc::new(hook));
+    }
+
+    #[cfg(feature = "gzip")]
+    pub fn set_compression(&mut self, compress: bool) {
+        self.config.compress = compress;
+    }
+
+    /// Lets POST requests carry their real method in `X-HTTP-Method-Override`, off by default.
+    pub fn allow_method_override(&mut self, allow: bool) {
+        self.config.method_override = allow;
+    }
+
+    /// Rejects GET and HEAD requests carrying a body with 400, off by default.
+    pub fn set_strict(&mut self, strict: bool) {
+        self.config.strict = strict;
+    }
+
+    /// Sets the `Server` header for responses without one, the crate name and version by default.
+    pub fn server_name(&mut self, name: &str) {
+        self.config.server_name = Some(name.into());
+    }
+
+    /// Stops adding a `Server` header to responses.
+    pub fn hide_server_name(&mut self) {
+        self.config.server_name = None;
+    }
+
+    /// Echoes TRACE requests back as `message/http`, off by default since it reflects headers.
+    pub fn allow_trace(&mut self, allow: bool) {
+        self.config.trace = allow;
+    }
+
+    /// Answers CORS preflights and marks responses to allowed origins.
+    pub fn set_cors(&mut self, cors: Cors) {
+        self.config.cors = Some(cors);
+    }
+
+    /// Adds a middleware layer; the first one added runs outermost.
+    pub fn wrap<M: Middleware + 'static>(&mut self, middleware: M) {
+        self.config.middleware.push(sync::Arc::new(middleware));
+    }
+
+    pub fn serve<F: Fn(&Message) -> Message>(&mut self, handler: F) {
+        let Some(stream) = self.accept() else {
+            return;
+        };
+
+        Self::handle(stream, &handler, &self.config);
+    }
+
+    pub fn serve_router(&mut self, router: &Router) {
+        self.serve(|request| router.handle(request))
+    }
+
+    pub fn serve_streaming<F>(&mut self, handler: F)
+    where
+        F: Fn(&Message, &mut BodyReader<&mut Stream>) -> Message,
+    {
+        let Some(stream) = self.accept() else {
+            return;
+        };
+
+        Self::handle_streaming(stream, &handler, &self.config);
+    }
+
+    fn accept(&self) -> Option<net::TcpStream> {
+        let [listener] = &self.listeners[..] else {
+            return self.poll();
+        };
+
+        listener.accept().ok().map(|(stream, _)| stream)
+    }
+
+    /// Waits for a connection on any of several listeners by polling them without blocking.
+    fn poll(&self) -> Option<net::TcpStream> {
+        for listener in &self.listeners {
+            listener.set_nonblocking(true).ok()?;
+        }
+
+        let accepted = loop {
+            let accepted = self.listeners.iter().find_map(|listener| match listener.accept() {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => None,
+                accepted => Some(accepted),
+            });
+
+            match accepted {
+                Some(accepted) => break accepted,
+                None => thread::sleep(ACCEPT_POLL_INTERVAL),
+            }
+        };
+
+        for listener in &self.listeners {
+            listener.set_nonblocking(false).ok();
+        }
+
+        let (stream, _) = accepted.ok()?;
+
+        stream.set_nonblocking(false).ok()?;
+
+        Some(stream)
+    }
+
+    pub fn run<F>(&mut self, handler: F)
+    where
+        F: Fn(&Message) -> Message + Send + Sync + 'static,
+    {
+        let handler = sync::Arc::new(handler);
+
+        let (sender, receiver) = mpsc::sync_channel::<net::TcpStream>(self.workers);
+
+        let receiver = sync::Arc::new(sync::Mutex::new(receiver));
+
+        let mut workers = vec![];
+
+        for _ in 0..self.workers {
+            let handler = handler.clone();
+            let receiver = receiver.clone();
+            let config = self.config.clone();
+
+            workers.push(thread::spawn(move || loop {
+                let Ok(stream) = receiver.lock().expect("worker queue poisoned").recv() else {
+                    break;
+                };
+
+                // A panicking handler loses its own connection rather than the worker.
+                let handle = panic::AssertUnwindSafe(|| Self::handle(stream, &*handler, &config));
+
+                panic::catch_unwind(handle).ok();
+            }));
+        }
+
+        let shutdown = &self.shutdown;
+
+        thread::scope(|scope| {
+            for listener in &self.listeners {
+                let sender = sender.clone();
+
+                scope.spawn(move || {
+                    for stream in listener.incoming() {
+                        if shutdown.load(atomic::Ordering::SeqCst) {
+                            break;
+                        }
+
+                        let Ok(stream) = stream else {
+                            continue;
+                        };
+
+                        if sender.send(stream).is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        drop(sender);
+
+        for worker in workers {
+            worker.join().ok();
+        }
+    }
+
+    fn handle<F>(stream: net::TcpStream, handler: &F, config: &Config)
+    where
+        F: Fn(&Message) -> Message,
+    {
+        let socket = stream.try_clone().ok();
+
+        let Ok(stream) = Stream::new(stream, config) else {
+            return;
+        };
+
+        let buffer = config.buffers.take();
+
+        let mut connection = Connection { stream, config: config.clone(), buffer, socket };
+
+        connection.serve(handler);
+
+        connection.stream.close();
+
+        config.buffers.give(connection.buffer);
+    }
+
+    fn handle_streaming<F>(stream: net::TcpStream, handler: &F, config: &Config)
+    where
+        F: Fn(&Message, &mut BodyReader<&mut Stream>) -> Message,
+    {
+        let socket = stream.try_clone().ok();
+
+        let Ok(stream) = Stream::new(stream, config) else {
+            return;
+        };
+
+        let buffer = config.buffers.take();
+
+        let mut connection = Connection { stream, config: config.clone(), buffer, socket };
+
+        connection.serve_streaming(handler);
+
+        connection.stream.close();
+
+        config.buffers.give(connection.buffer);
+    }
+}
+
+pub struct Stream {
+    tcp: net::TcpStream,
+    #[cfg(feature = "tls")]
+    tls: Option<Box<rustls::ServerConnection>>,
+}
+
+impl Stream {
+    fn new(tcp: net::TcpStream, config: &Config) -> Result<Self> {
+        tcp.set_read_timeout(config.read_timeout)?;
+        tcp.set_write_timeout(config.write_timeout)?;
+
+        #[cfg(feature = "tls")]
+        let tls = match &config.tls {
+            Some(tls) => Some(Box::new(
+                rustls::ServerConnection::new(tls.clone()).map_err(|e| Error::Tls(e.to_string()))?,
+            )),
+            None => None,
+        };
+
+        Ok(Self {
+            tcp,
+            #[cfg(feature = "tls")]
+            tls,
+        })
+    }
+
+    pub fn peer_addr(&self) -> io::Result<net::SocketAddr> {
+        self.tcp.peer_addr()
+    }
+
+    fn close(&mut self) {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            use io::Write;
+
+            tls.send_close_notify();
+
+            rustls::Stream::new(&mut **tls, &mut self.tcp).flush().ok();
+        }
+
+        self.tcp.shutdown(net::Shutdown::Both).ok();
+    }
+}
+
+impl io::Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            return rustls::Stream::new(&mut **tls, &mut self.tcp).read(buf);
+        }
+
+        self.tcp.read(buf)
+    }
+}
+
+impl io::Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            return rustls::Stream::new(&mut **tls, &mut self.tcp).write(buf);
+        }
+
+        self.tcp.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = &mut self.tls {
+            return rustls::Stream::new(&mut **tls, &mut self.tcp).flush();
+        }
+
+        self.tcp.flush()
+    }
+}
+
+pub struct Connection<S = net::TcpStream> {
+    stream: S,
+    config: Config,
+    buffer: Vec<u8>,
+    socket: Option<net::TcpStream>,
+}
+
+impl<S: io::Read + io::Write> Connection<S> {
+    pub fn new(stream: S) -> Self {
+        Self { stream, config: Config::default(), buffer: vec![], socket: None }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// Reads the next request, answering 400, 431 or 505 itself when it cannot be served.
+    pub fn read_request(&mut self) -> Result<Message> {
+        let length = self.read_message()?;
+
+        if length == 0 {
+            Err(io::Error::from(io::ErrorKind::UnexpectedEof))?
+        }
+
+        let request = Message::parse(&self.buffer[..length]);
+
+        self.buffer.drain(..length);
+
+        let request = match request {
+            Ok(request) => request,
+            Err(Error::UnsupportedVersion) => {
+                self.reject(Code::HttpVersionNotSupported);
+
+                Err(Error::UnsupportedVersion)?
+            }
+            Err(e) => {
+                self.reject(Code::BadRequest);
+
+                Err(e)?
+            }
+        };
+
+        if !Self::supported(&request) {
+            self.reject(Code::HttpVersionNotSupported);
+
+            Err(Error::Malformed(MalformedKind::Version))?
+        }
+
+        if Self::unexpected_body(&self.config, &request) {
+            self.reject(Code::BadRequest);
+
+            Err(Error::Malformed(MalformedKind::UnexpectedBody))?
+        }
+
+        Ok(request)
+    }
+
+    pub fn write_response(&mut self, response: Message) -> Result<()> {
+        Ok(response.write_to(&mut self.stream)?)
+    }
+
+    pub fn serve<F>(&mut self, handler: &F)
+    where
+        F: Fn(&Message) -> Message,
+    {
+        while let Ok(mut request) = self.read_request() {
+            Self::override_method(&self.config, &mut request);
+
+            Self::requested(&self.config, &request, request.body().len());
+
+            let started = time::Instant::now();
+
+            let builtin = Self::preflight(&self.config, &request)
+                .or_else(|| Self::trace(&self.config, &request));
+
+            let response = match builtin {
+                Some(response) => response,
+                None => Self::dispatch(&self.config.middleware, &request, handler),
+            };
+
+            if !self.respond(&request, response, started.elapsed(), true) {
+                break;
+            }
+        }
+    }
+
+    pub fn serve_streaming<F>(&mut self, handler: &F)
+    where
+        F: Fn(&Message, &mut BodyReader<&mut S>) -> Message,
+    {
+        loop {
+            let buffer = mem::take(&mut self.buffer);
+
+            let max_header_bytes = self.config.max_header_bytes;
+
+            let head = Message::read_head_buffered(&mut self.stream, buffer, max_header_bytes);
+
+            let (mut request, mut body) = match head {
+                Ok(head) => head,
+                Err(Error::Malformed(MalformedKind::HeaderTooLarge)) => {
+                    self.reject(Code::RequestHeaderFieldsTooLarge);
+                    break;
+                }
+                Err(Error::UnsupportedVersion) => {
+                    self.reject(Code::HttpVersionNotSupported);
+                    break;
+                }
+                Err(Error::Malformed(_)) => {
+                    self.reject(Code::BadRequest);
+                    break;
+                }
+                Err(_) => break,
+            };
+
+            if !Self::supported(&request) {
+                self.reject(Code::HttpVersionNotSupported);
+                break;
+            }
+
+            if Self::unexpected_body(&self.config, &request) {
+                self.reject(Code::BadRequest);
+                break;
+            }
+
+            Self::override_method(&self.config, &mut request);
+
+            let declared = request.headers().get("Content-Length");
+
+            let declared = declared.and_then(|length| Message::parse_length(length).ok());
+
+            if declared.is_some_and(|length: usize| length > self.config.max_body_bytes) {
+                self.reject(Code::PayloadTooLarge);
+                break;
+            }
+
+            body.limit = self.config.max_body_bytes;
+            let version = request.version();
+
+            let socket = self.socket.as_ref();
+
+            let headers = request.headers();
+
+            let expect =
+                Self::expect(&mut body.reader, socket, &self.config, version, headers, false);
+
+            if !expect.unwrap_or(false) {
+                self.reject(Code::ExpectationFailed);
+                break;
+            }
+
+            Self::requested(&self.config, &request, declared.unwrap_or_default());
+
+            let started = time::Instant::now();
+
+            let builtin = Self::preflight(&self.config, &request)
+                .or_else(|| Self::trace(&self.config, &request));
+
+            let response = match builtin {
+                Some(response) => response,
+                None => {
+                    let body = cell::RefCell::new(&mut body);
+
+                    let handler = |request: &Message| handler(request, &mut body.borrow_mut());
+
+                    Self::dispatch(&self.config.middleware, &request, &handler)
+                }
+            };
+
+            let elapsed = started.elapsed();
+
+            let drained = io::copy(&mut body, &mut io::sink()).is_ok();
+
+            if body.exceeded {
+                self.reject(Code::PayloadTooLarge);
+                break;
+            }
+
+            self.buffer = mem::take(&mut body.buffer);
+
+            if request.headers().contains("Expect") {
+                self.restore_timeout();
+            }
+
+            if !self.respond(&request, response, elapsed, drained) {
+                break;
+            }
+        }
+    }
+
+    fn respond(
+        &mut self,
+        request: &Message,
+        mut response: Message,
+        elapsed: time::Duration,
+        reusable: bool,
+    ) -> bool {
+        let keep_alive = Self::prepare(request, &mut response, &self.config) && reusable;
+
+        let code = response.code().unwrap_or(Code::Success);
+
+        let encoded = response.encode(!matches!(request.method(), Some(Method::Head)));
+
+        let sent = encoded.write_to(&mut self.stream);
+
+        if let (Some(hook), Some(method)) = (&self.config.on_response, request.method()) {
+            hook(&ResponseInfo {
+                method: method.clone(),
+                path: request.path().to_string(),
+                code,
+                bytes: sent.as_ref().copied().unwrap_or_default(),
+                elapsed,
+                error: sent.as_ref().err().map(io::Error::kind),
+            });
+        }
+
+        sent.is_ok() && keep_alive
+    }
+
+    fn requested(config: &Config, request: &Message, bytes: usize) {
+        if let (Some(hook), Some(method)) = (&config.on_request, request.method()) {
+            hook(&RequestInfo { method: method.clone(), path: request.path().to_string(), bytes });
+        }
+    }
+
+    fn override_method(config: &Config, request: &mut Message) {
+        if !config.method_override {
+            return;
+        }
+
+        let Some(Ok(overridden)) =
+            request.headers().get("X-HTTP-Method-Override").map(|value| value.trim().parse())
+        else {
+            return;
+        };
+
+        if let Message::Request { method, .. } = request {
+            if *method == Method::Post {
+                *method = overridden;
+            }
+        }
+    }
+
+    fn dispatch<F>(middleware: &[Layer], request: &Message, handler: &F) -> Message
+    where
+        F: Fn(&Message) -> Message,
+    {
+        match middleware.split_first() {
+            Some((layer, rest)) => {
+                layer.handle(request, &|request| Self::dispatch(rest, request, handler))
+            }
+            None => handler(request),
+        }
+    }
+
+    fn preflight(config: &Config, request: &Message) -> Option<Message> {
+        let cors = config.cors.as_ref()?;
+
+        if request.method() != Some(&Method::Options) {
+            None?
+        }
+
+        let headers = request.headers();
+
+        let origin = headers.get("Origin")?;
+
+        let requested = headers.get("Access-Control-Request-Method")?;
+
+        Some(cors.preflight(origin, requested))
+    }
+
+    /// Reflects the request line and headers, leaving out credentials as RFC 9110 advises.
+    fn trace(config: &Config, request: &Message) -> Option<Message> {
+        if !config.trace || request.method() != Some(&Method::Trace) {
+            None?
+        }
+
+        let (method, target) = (request.method()?, request.target()?);
+
+        let mut echo = format!("{} {} HTTP/{}\r\n", method, target, request.version());
+
+        for header in request.headers() {
+            let secret = ["Authorization", "Proxy-Authorization", "Cookie"]
+                .iter()
+                .any(|name| header.name.eq_ignore_ascii_case(name));
+
+            if !secret {
+                echo += &format!("{}: {}\r\n", header.name, header.value);
+            }
+        }
+
+        echo += "\r\n";
+
+        let mut message = MessageBuilder::new().code(Code::Success);
+
+        headers! { message, "Content-Type": "message/http" };
+
+        body! { message, echo };
+
+        Some(message.build())
+    }
+
+    fn expect<W: io::Write>(
+        writer: &mut W,
+        socket: Option<&net::TcpStream>,
+        config: &Config,
+        version: Version,
+        headers: &Headers,
+        complete: bool,
+    ) -> io::Result<bool> {
+        let Some(expectation) = headers.get("Expect") else {
+            return Ok(true);
+        };
+
+        if !expectation.trim().eq_ignore_ascii_case("100-continue") {
+            return Ok(false);
+        }
+
+        if !complete && version >= Version::HTTP_11 {
+            writer.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+
+            writer.flush()?;
+
+            // A client that asked to continue and then stalls must not hold the worker for the
+            // full read timeout.
+            if let Some(socket) = socket {
+                socket.set_read_timeout(Some(config.continue_timeout))?;
+            }
+        }
+
+        Ok(true)
+    }
+
+    fn restore_timeout(&self) {
+        if let Some(socket) = &self.socket {
+            socket.set_read_timeout(self.config.read_timeout).ok();
+        }
+    }
+
+    fn reject(&mut self, code: Code) {
+        Self::status(code).write_to(&mut self.stream).ok();
+    }
+
+    fn supported(request: &Message) -> bool {
+        match request {
+            Message::Request { version, .. } => {
+                !request.is_preface() && matches!(*version, Version::HTTP_10 | Version::HTTP_11)
+            }
+            Message::Response { .. } => false,
+        }
+    }
+
+    /// In strict mode a GET or HEAD request may not carry a body.
+    fn unexpected_body(config: &Config, request: &Message) -> bool {
+        let bodiless = matches!(request.method(), Some(Method::Get | Method::Head));
+
+        let headers = request.headers();
+
+        let declared = headers.get("Content-Length").and_then(|l| l.parse::<usize>().ok());
+
+        let chunked = headers.contains("Transfer-Encoding");
+
+        config.strict && bodiless && (chunked || declared.is_some_and(|length| length > 0))
+    }
+
+    fn prepare(request: &Message, response: &mut Message, config: &Config) -> bool {
+        Self::conditional(request, response);
+
+        #[cfg(feature = "gzip")]
+        if config.compress && request.accepts_encoding("gzip") {
+            response.gzip();
+        }
+
+        if let (Some(cors), Some(origin)) = (&config.cors, request.headers().get("Origin")) {
+            cors.apply(origin, response);
+        }
+
+        Self::date(response);
+
+        if let Some(name) = &config.server_name {
+            if !response.headers().contains("Server") {
+                response.push_header(Header { name: "Server".into(), value: name.clone() });
+            }
+        }
+
+        if request.version() < Version::HTTP_11 && Self::unchunk(response) {
+            response.push_header(Header { name: "Connection".into(), value: "close".into() });
+        }
+
+        Self::connection(request, response)
+    }
+
+    /// HTTP/1.0 clients cannot read chunks, so a streamed body goes out raw and ends on close.
+    fn unchunk(response: &mut Message) -> bool {
+        if !response.is_streamed() || !response.has_token("Transfer-Encoding", "chunked") {
+            return false;
+        }
+
+        response.headers_mut().remove("Transfer-Encoding");
+
+        true
+    }
+
+    fn conditional(request: &Message, response: &mut Message) {
+        if !matches!(response.code(), Some(Code::Success)) {
+            return;
+        }
+
+        let headers = response.headers();
+
+        let etag = headers.get("ETag");
+
+        let last_modified = headers.get("Last-Modified").and_then(parse_http_date);
+
+        if (etag.is_none() && last_modified.is_none())
+            || !request.is_not_modified(etag, last_modified)
+        {
+            return;
+        }
+
+        if let Message::Response { code, frames, .. } = response {
+            *code = Code::NotModified;
+
+            frames.retain(|frame| matches!(frame, Frame::Headers { .. }));
+        }
+
+        let headers = response.headers_mut();
+
+        headers.remove("Content-Length");
+
+        headers.remove("Transfer-Encoding");
+    }
+
+    fn connection(request: &Message, response: &mut Message) -> bool {
+        let keep_alive = request.keep_alive();
+
+        if response.header_values("Connection").is_empty() {
+            let value = if keep_alive { "keep-alive" } else { "close" };
+
+            response.push_header(Header { name: "Connection".into(), value: value.into() });
+
+            keep_alive
+        } else {
+            keep_alive && !response.has_token("Connection", "close")
+        }
+    }
+
+    fn status(code: Code) -> Message {
+        let mut message = Message::error(code);
+
+        message.push_header(Header { name: "Connection".into(), value: "close".into() });
+
+        Self::date(&mut message);
+
+        message
+    }
+
+    fn date(response: &mut Message) {
+        if response.header_values("Date").is_empty() {
+            let date = http_date(time::SystemTime::now());
+
+            response.push_header(Header { name: "Date".into(), value: date });
+        }
+    }
+
+    fn read_message(&mut self) -> Result<usize> {
+        let mut octets = [0; READ_BUFFER_BYTES];
+
+        let mut checked = false;
+
+        let mut expected = None;
+
+        let mut expecting = false;
+
+        let length = loop {
+            if !self.buffer.is_empty() {
+                if let Some(head) = find_bytes(&self.buffer, b"\r\n\r\n") {
+                    if head + 4 > self.config.max_header_bytes {
+                        self.reject(Code::RequestHeaderFieldsTooLarge);
+
+                        Err(Error::Malformed(MalformedKind::HeaderTooLarge))?
+                    }
+
+                    let limit = head + 4 + self.config.max_body_bytes;
+
+                    // A length once known does not change as the body arrives, so only scan
+                    // the head again while it is still unknown.
+                    if expected.is_none() {
+                        expected = match Message::expected_len(&self.buffer) {
+                            Ok(expected) => expected,
+                            Err(kind) => {
+                                self.reject(Code::BadRequest);
+
+                                Err(Error::Malformed(kind))?
+                            }
+                        };
+                    }
+
+                    match expected {
+                        Some(length) if length > limit => {
+                            self.reject(Code::PayloadTooLarge);
+
+                            Err(Error::Malformed(MalformedKind::BodyTooLarge))?
+                        }
+                        None if self.buffer.len() > limit => {
+                            self.reject(Code::PayloadTooLarge);
+
+                            Err(Error::Malformed(MalformedKind::BodyTooLarge))?
+                        }
+                        _ => {}
+                    }
+
+                    let complete = expected.is_some_and(|length| self.buffer.len() >= length);
+
+                    if !checked {
+                        checked = true;
+
+                        if let Some(length) = expected {
+                            self.buffer.reserve(length.saturating_sub(self.buffer.len()));
+                        }
+
+                        let head = Message::parse_head(&self.buffer[..head]);
+
+                        if let Ok((StartLine::Request { version, .. }, headers)) = head {
+                            expecting = headers.contains("Expect");
+
+                            let socket = self.socket.as_ref();
+
+                            let (stream, config) = (&mut self.stream, &self.config);
+
+                            if !Self::expect(stream, socket, config, version, &headers, complete)? {
+                                self.reject(Code::ExpectationFailed);
+
+                                Err(Error::Malformed(MalformedKind::Expectation))?
+                            }
+                        }
+                    }
+
+                    if let (true, Some(length)) = (complete, expected) {
+                        if expecting {
+                            self.restore_timeout();
+                        }
+
+                        break length;
+                    }
+                } else if self.buffer.len() > self.config.max_header_bytes {
+                    self.reject(Code::RequestHeaderFieldsTooLarge);
+
+                    Err(Error::Malformed(MalformedKind::HeaderTooLarge))?
+                }
+            }
+
+            let read = self.stream.read(&mut octets);
+
+            if let Ok(length) = read {
+                if length == 0 {
+                    break self.buffer.len();
+                }
+
+                self.buffer.extend_from_slice(&octets[..length]);
+            } else if let Err(e) = read {
+                match e.kind() {
+                    io::ErrorKind::Interrupted => continue,
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                        Err(io::Error::new(io::ErrorKind::TimedOut, e))?
+                    }
+                    _ => break self.buffer.len(),
+                }
+            }
+        };
+
+        Ok(length)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Read, Write};
+
+    fn exchange(addr: net::SocketAddr, request: &[u8]) -> String {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+
+        stream.set_read_timeout(Some(time::Duration::from_secs(10))).unwrap();
+
+        stream.write_all(request).unwrap();
+
+        // A server refusing an oversized request may close before reading all of it.
+        stream.shutdown(net::Shutdown::Write).ok();
+
+        let mut response = String::new();
+
+        stream.read_to_string(&mut response).ok();
+
+        response
+    }
+
+    /// Serves `connections` connections on a background thread.
+    fn spawn<F>(
+        mut server: Server,
+        connections: usize,
+        handler: F,
+    ) -> (net::SocketAddr, thread::JoinHandle<()>)
+    where
+        F: Fn(&Message) -> Message + Send + 'static,
+    {
+        let addr = server.local_addr().unwrap();
+
+        let worker = thread::spawn(move || {
+            for _ in 0..connections {
+                server.serve(&handler);
+            }
+        });
+
+        (addr, worker)
+    }
+
+    fn get(target: &str) -> Message {
+        Message::parse(format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", target).as_bytes()).unwrap()
+    }
+
+    fn ok(_request: &Message, _params: &Params) -> Message {
+        MessageBuilder::new().build()
+    }
+
+    fn request(method: &str, target: &str) -> Message {
+        let request = format!("{} {} HTTP/1.1\r\nHost: a\r\n\r\n", method, target);
+
+        Message::parse(request.as_bytes()).unwrap()
+    }
+
+    fn echo(request: &Message) -> Message {
+        MessageBuilder::new().body(request.body()).build()
+    }
+
+    fn get_with(name: &str, value: &str) -> Message {
+        let request = format!("GET / HTTP/1.1\r\nHost: a\r\n{}: {}\r\n\r\n", name, value);
+
+        Message::parse(request.as_bytes()).unwrap()
+    }
+
+    /// Replays `input` as a connection, counting the reads it serves and keeping what is written.
+    struct Recorded {
+        input: io::Cursor<Vec<u8>>,
+        reads: usize,
+        output: Vec<u8>,
+    }
+
+    impl Recorded {
+        fn new(input: Vec<u8>) -> Self {
+            Self { input: io::Cursor::new(input), reads: 0, output: vec![] }
+        }
+    }
+
+    impl io::Read for Recorded {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+
+            self.input.read(buf)
+        }
+    }
+
+    impl io::Write for Recorded {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn code_reason_phrases_in_status_line() {
+        for (code, line) in [
+            (Code::Success, "HTTP/1.1 200 OK\r\n"),
+            (Code::NotFound, "HTTP/1.1 404 Not Found\r\n"),
+            (Code::InternalServerError, "HTTP/1.1 500 Internal Server Error\r\n"),
+        ] {
+            let bytes = MessageBuilder::new().code(code).build().into_bytes();
+
+            assert_eq!(bytes, format!("{}Content-Length: 0\r\n\r\n", line).into_bytes());
+        }
+    }
+
+    #[test]
+    fn lowercase_content_length_keeps_body() {
+        let request = b"POST /form HTTP/1.1\r\nHost: a\r\ncontent-length: 5\r\n\r\nhello";
+
+        let Ok(Message::Request { frames, .. }) = Message::parse(request) else {
+            panic!("request did not parse");
+        };
+
+        assert!(matches!(&frames[..], [_, Frame::Data { payload }] if payload == b"hello"));
+    }
+
+    #[test]
+    fn header_values_collects_repeated_headers() {
+        let request = b"GET / HTTP/1.1\r\nHost: a\r\n\
+            Accept: text/html\r\naccept: application/json\r\n\r\n";
+
+        let request = Message::parse(request).unwrap();
+
+        assert_eq!(request.header_values("ACCEPT"), ["text/html", "application/json"]);
+        assert!(request.header_values("Cookie").is_empty());
+    }
+
+    #[test]
+    fn request_split_across_writes() {
+        let mut server = Server::bind(0).unwrap();
+
+        let addr = server.local_addr().unwrap();
+
+        let worker = thread::spawn(move || server.serve(echo));
+
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+
+        stream.set_read_timeout(Some(time::Duration::from_millis(100))).unwrap();
+
+        let mut response = String::new();
+
+        for part in ["POST / HTTP/1.1\r\nHost: a\r\nContent-", "Length: 5\r\n\r\nhel"] {
+            stream.write_all(part.as_bytes()).unwrap();
+
+            // Nothing may be answered until the whole body has arrived.
+            assert!(stream.read_to_string(&mut response).is_err(), "{}", response);
+        }
+
+        stream.write_all(b"lo").unwrap();
+
+        // The connection is kept alive, so end it to read the response to completion.
+        stream.shutdown(net::Shutdown::Write).unwrap();
+
+        stream.set_read_timeout(Some(time::Duration::from_secs(10))).unwrap();
+
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn chunked_body_is_decoded() {
+        let request = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nHello\r\n0\r\n\r\n";
+
+        let Ok(Message::Request { frames, .. }) = Message::parse(request) else {
+            panic!("request did not parse");
+        };
+
+        assert!(matches!(&frames[..], [_, Frame::Data { payload }] if payload == b"Hello"));
+
+        assert_eq!(Message::expected_len(request).unwrap(), Some(request.len()));
+    }
+
+    #[test]
+    fn oversized_chunk_size_is_malformed() {
+        let request = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+            FFFFFFFFFFFFFFFF\r\nab\r\n0\r\n\r\n";
+
+        assert!(matches!(Message::parse(request), Err(Error::Malformed(MalformedKind::Chunk))));
+
+        let mut server = Server::bind(0).unwrap();
+
+        let addr = server.local_addr().unwrap();
+
+        let handler = |_: &Message| MessageBuilder::new().build();
+
+        let worker = thread::spawn(move || server.serve(handler));
+
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+
+        stream.write_all(request).unwrap();
+
+        stream.shutdown(net::Shutdown::Write).unwrap();
+
+        stream.read_to_end(&mut vec![]).ok();
+
+        // An overflowing size used to panic the thread reading the request.
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn chunked_serialization() {
+        let response = MessageBuilder::new()
+            .header(Header { name: "Transfer-Encoding".into(), value: "chunked".into() })
+            .body("Hello")
+            .build();
+
+        let expected = "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nHello\r\n0\r\n\r\n";
+
+        assert_eq!(String::from_utf8(response.into_bytes()).unwrap(), expected);
+    }
+
+    #[test]
+    fn content_length_counts_bytes() {
+        let buffer = "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\néGET";
+
+        let (request, consumed) = Message::parse_with_len(buffer.as_bytes()).unwrap();
+
+        assert_eq!(request.body(), "é".as_bytes());
+        assert_eq!(&buffer[consumed..], "GET");
+    }
+
+    #[test]
+    fn run_serves_concurrent_clients() {
+        let mut server = Server::bind(0).unwrap().with_workers(4);
+
+        let addr = server.local_addr().unwrap();
+
+        // The pool serves until the process exits, so the thread is left detached.
+        thread::spawn(move || {
+            server.run(|request: &Message| {
+                MessageBuilder::new().body(request.header_values("X-Client")[0]).build()
+            })
+        });
+
+        let clients = (0..16)
+            .map(|client| {
+                thread::spawn(move || {
+                    let request =
+                        format!("GET / HTTP/1.1\r\nHost: a\r\nX-Client: {}\r\n\r\n", client);
+
+                    let response = exchange(addr, request.as_bytes());
+
+                    assert!(response.ends_with(&format!("\r\n\r\n{}", client)), "{}", response);
+                })
+            })
+            .collect::<Vec<_>>();
+
+        for client in clients {
+            client.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn run_survives_panicking_handlers() {
+        let mut server = Server::bind(0).unwrap().with_workers(2);
+
+        let addr = server.local_addr().unwrap();
+
+        thread::spawn(move || {
+            server.run(|request: &Message| {
+                if !request.header_values("X-Panic").is_empty() {
+                    panic!("handler failed");
+                }
+
+                MessageBuilder::new().body("ok").build()
+            })
+        });
+
+        for _ in 0..4 {
+            exchange(addr, b"GET / HTTP/1.1\r\nHost: a\r\nX-Panic: 1\r\n\r\n");
+        }
+
+        let response = exchange(addr, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+
+        assert!(response.ends_with("\r\n\r\nok"), "{}", response);
+    }
+
+    #[test]
+    fn idle_connection_is_dropped_after_read_timeout() {
+        let mut server = Server::bind(0).unwrap();
+
+        server.set_read_timeout(Some(time::Duration::from_millis(200)));
+
+        let addr = server.local_addr().unwrap();
+
+        let worker = thread::spawn(move || server.serve(echo));
+
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+
+        stream.set_read_timeout(Some(time::Duration::from_secs(10))).unwrap();
+
+        let started = time::Instant::now();
+
+        let mut response = vec![];
+
+        stream.read_to_end(&mut response).unwrap();
+
+        assert!(started.elapsed() >= time::Duration::from_millis(200));
+        assert!(started.elapsed() < time::Duration::from_secs(5));
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn http_10_defaults_to_close() {
+        let parse = |request: &[u8]| Message::parse(request).unwrap().keep_alive();
+
+        assert!(!parse(b"GET / HTTP/1.0\r\n\r\n"));
+        assert!(parse(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n"));
+        assert!(parse(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert!(!parse(b"GET / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n"));
+
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, echo);
+
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+
+        stream.set_read_timeout(Some(time::Duration::from_secs(10))).unwrap();
+
+        stream.write_all(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+
+        // The server closes the connection on its own, so reading to the end does not time out.
+        let mut response = String::new();
+
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.contains("\r\nConnection: close\r\n"), "{}", response);
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn garbage_request_is_answered_with_400() {
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, echo);
+
+        let response = exchange(addr, b"\x00\x01 not http at all\r\n\r\n");
+
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{}", response);
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn header_names_must_be_tokens() {
+        let request = b"GET / HTTP/1.1\r\nHost: a\r\nBad Name: x\r\n\r\n";
+
+        assert!(matches!(Message::parse(request), Err(Error::Malformed(MalformedKind::Header))));
+
+        let request = Message::parse(b"GET / HTTP/1.1\r\nHost: a\r\nX-Empty:\r\n\r\n").unwrap();
+
+        assert_eq!(request.header_values("X-Empty"), [""]);
+    }
+
+    #[test]
+    fn header_value_keeps_its_colons() {
+        let request = Message::parse(b"GET / HTTP/1.1\r\nHost: example.com:8080\r\n\r\n").unwrap();
+
+        assert_eq!(request.header_values("Host"), ["example.com:8080"]);
+    }
+
+    #[test]
+    fn query_pairs_are_decoded() {
+        let request = get("/search?q=hello%20world&lang=en");
+
+        assert_eq!(request.path(), "/search");
+        assert_eq!(request.query(), Some("q=hello%20world&lang=en"));
+
+        let pairs = request.query_pairs().collect::<Vec<_>>();
+
+        assert_eq!(pairs, [("q".into(), "hello world".into()), ("lang".into(), "en".into())]);
+    }
+
+    #[test]
+    fn decoded_path_handles_escapes() {
+        assert_eq!(get("/files/my%20file.txt?x=%20").decoded_path().unwrap(), "/files/my file.txt");
+
+        assert!(matches!(get("/files/%zz").decoded_path(), Err(Error::Malformed(_))));
+    }
+
+    #[test]
+    fn router_captures_path_params() {
+        let router = Router::new()
+            .get("/users/:id/posts/:post_id", |_: &Message, params: &Params| {
+                let (id, post) = (params.get("id").unwrap(), params.get("post_id").unwrap());
+
+                let body = format!("{} {}", id, post);
+
+                MessageBuilder::new().body(&body).build()
+            })
+            .get("/users/me/posts/:post_id", |_: &Message, _: &Params| {
+                MessageBuilder::new().body("static").build()
+            });
+
+        assert_eq!(router.handle(&get("/users/42/posts/7")).body(), b"42 7");
+        assert_eq!(router.handle(&get("/users/me/posts/7")).body(), b"static");
+        assert!(matches!(router.handle(&get("/users/42/posts")).code(), Some(Code::NotFound)));
+    }
+
+    #[test]
+    fn http_11_requires_one_host() {
+        let parses = |request: &[u8]| Message::parse(request).is_ok();
+
+        assert!(!parses(b"GET / HTTP/1.1\r\n\r\n"));
+        assert!(!parses(b"GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n"));
+        assert!(parses(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n"));
+        assert!(parses(b"GET / HTTP/1.0\r\n\r\n"));
+    }
+
+    #[test]
+    fn extension_methods_round_trip() {
+        let request = Message::parse(b"PROPFIND /x HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+
+        assert_eq!(request.method(), Some(&Method::Extension("PROPFIND".into())));
+        assert_eq!(request.method().unwrap().to_string(), "PROPFIND");
+
+        let request = Message::parse(b"PROP{FIND /x HTTP/1.1\r\nHost: a\r\n\r\n");
+
+        assert!(matches!(request, Err(Error::Malformed(MalformedKind::Method))));
+    }
+
+    #[test]
+    fn content_type_parses_parameters() {
+        let request = b"POST / HTTP/1.1\r\nHost: a\r\n\
+            Content-Type: multipart/form-data; boundary=----abc\r\n\r\n";
+
+        let media = Message::parse(request).unwrap().content_type().unwrap();
+
+        assert!(media.is("multipart/form-data"));
+        assert_eq!(media.boundary(), Some("----abc"));
+        assert_eq!(media.charset(), None);
+
+        let header = "Text/HTML; Charset=\"utf-8\"; title=\"a \\\"b\\\"; c\"";
+
+        let media = MediaType::try_from(header).unwrap();
+
+        assert_eq!((media.kind(), media.subtype()), ("text", "html"));
+        assert_eq!(media.charset(), Some("utf-8"));
+        assert_eq!(media.param("title"), Some("a \"b\"; c"));
+        assert_eq!(media.to_string(), "text/html; charset=utf-8; title=\"a \\\"b\\\"; c\"");
+
+        assert!(MediaType::try_from("text/html; charset=\"utf-8").is_err());
+    }
+
+    #[test]
+    fn form_data_decodes_pairs() {
+        let request = b"POST / HTTP/1.1\r\nHost: a\r\n\
+            Content-Type: application/x-www-form-urlencoded\r\nContent-Length: 26\r\n\r\n\
+            name=John+Doe&age=30&x=%21";
+
+        let pairs = Message::parse(request).unwrap().form_data().unwrap();
+
+        let expected = [("name", "John Doe"), ("age", "30"), ("x", "!")];
+
+        assert_eq!(pairs, expected.map(|(key, value)| (key.to_string(), value.to_string())));
+
+        assert!(get("/?name=John").form_data().is_none());
+    }
+
+    #[test]
+    fn multipart_form_with_text_and_file() {
+        let body = "--XyZ\r\n\
+            Content-Disposition: form-data; name=\"title\"\r\n\r\n\
+            Holiday\r\n\
+            --XyZ\r\n\
+            Content-Disposition: form-data; name=\"photo\"; filename=\"a.png\"\r\n\
+            Content-Type: image/png\r\n\r\n\
+            PNG\r\ndata\r\n\
+            --XyZ--\r\n";
+
+        let request = format!(
+            "POST /upload HTTP/1.1\r\nHost: a\r\n\
+            Content-Type: multipart/form-data; boundary=XyZ\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+
+        let parts = Message::parse(request.as_bytes()).unwrap().multipart().unwrap();
+
+        assert_eq!(parts.len(), 2);
+
+        assert_eq!((parts[0].name(), parts[0].filename()), (Some("title"), None));
+        assert_eq!(parts[0].bytes(), b"Holiday");
+
+        assert_eq!((parts[1].name(), parts[1].filename()), (Some("photo"), Some("a.png")));
+        assert!(parts[1].content_type().unwrap().is("image/png"));
+        assert_eq!(parts[1].bytes(), b"PNG\r\ndata");
+    }
+
+    #[test]
+    fn oversized_head_is_refused() {
+        let filler = |length| {
+            format!("GET / HTTP/1.1\r\nHost: a\r\nX-Filler: {}\r\n\r\n", "a".repeat(length))
+        };
+
+        let head = filler(12 * 1024);
+
+        let read = Message::read_head(head.as_bytes());
+
+        assert!(matches!(read, Err(Error::Malformed(MalformedKind::HeaderTooLarge))));
+
+        // The whole head arrives in the second read, past the limit.
+        let head = filler(1400);
+
+        let read = Message::read_head_within(head.as_bytes(), 1024);
+
+        assert!(matches!(read, Err(Error::Malformed(MalformedKind::HeaderTooLarge))));
+
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, echo);
+
+        let response = exchange(addr, filler(12 * 1024).as_bytes());
+
+        assert!(response.starts_with("HTTP/1.1 431 "), "{}", response);
+
+        worker.join().unwrap();
+
+        let mut server = Server::bind(0).unwrap();
+
+        server.set_max_header_bytes(1024);
+
+        let (addr, worker) = spawn(server, 1, echo);
+
+        assert!(exchange(addr, filler(1000).as_bytes()).starts_with("HTTP/1.1 431 "));
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn build_computes_content_length() {
+        let response = MessageBuilder::new().body("hello world").build();
+
+        assert_eq!(response.headers().get("Content-Length"), Some("11"));
+    }
+
+    #[test]
+    fn bodiless_codes_drop_the_payload() {
+        for code in [Code::Continue, Code::NoContent, Code::NotModified] {
+            let response = Message::error(code);
+
+            assert!(response.body().is_empty());
+            assert!(!response.headers().contains("Content-Length"));
+
+            let bytes = response.into_bytes();
+
+            assert_eq!(find_bytes(&bytes, b"\r\n\r\n"), Some(bytes.len() - 4));
+        }
+    }
+
+    #[test]
+    fn http_date_formats_imf_fixdate() {
+        let time = time::UNIX_EPOCH + time::Duration::from_secs(784_111_777);
+
+        assert_eq!(http_date(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+    }
+
+    #[test]
+    fn head_response_has_no_body() {
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, |_: &Message| {
+            MessageBuilder::new().body("hello").build()
+        });
+
+        let response = exchange(addr, b"HEAD / HTTP/1.1\r\nHost: a\r\n\r\n");
+
+        assert!(response.contains("\r\nContent-Length: 5\r\n"), "{}", response);
+        assert!(response.ends_with("\r\n\r\n"), "{}", response);
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn options_lists_routed_methods() {
+        let router = Router::new().get("/users", ok).post("/users", ok).put("/users/:id", ok);
+
+        let response = router.handle(&request("OPTIONS", "/users"));
+
+        assert!(matches!(response.code(), Some(Code::NoContent)));
+        assert_eq!(response.headers().get("Allow"), Some("GET, POST, HEAD, OPTIONS"));
+
+        let response = router.handle(&request("OPTIONS", "*"));
+
+        assert_eq!(response.headers().get("Allow"), Some("GET, POST, PUT, HEAD, OPTIONS"));
+    }
+
+    #[test]
+    fn not_modified_compares_etags() {
+        let request = b"GET / HTTP/1.1\r\nHost: a\r\nIf-None-Match: \"v1\", \"v2\"\r\n\r\n";
+
+        let request = Message::parse(request).unwrap();
+
+        assert!(request.is_not_modified(Some("\"v2\""), None));
+        assert!(!request.is_not_modified(Some("\"v3\""), None));
+    }
+
+    #[test]
+    fn static_file_serves_and_guards() {
+        let root = std::env::temp_dir().join(format!("http-static-{}", std::process::id()));
+
+        fs::create_dir_all(root.join("css")).unwrap();
+
+        fs::write(root.join("css/site.css"), "body {}").unwrap();
+
+        let response = static_file(&root, "/css/site.css");
+
+        assert!(matches!(response.code(), Some(Code::Success)));
+        assert_eq!(response.headers().get("Content-Type"), Some("text/css; charset=utf-8"));
+        assert_eq!(response.headers().get("Content-Length"), Some("7"));
+        assert_eq!(response.body(), b"body {}");
+
+        let traversal = static_file(&root, "/css/../../etc/passwd");
+
+        assert!(matches!(traversal.code(), Some(Code::Forbidden)));
+        assert!(matches!(static_file(&root, "/%2e%2e/secret").code(), Some(Code::Forbidden)));
+        assert!(matches!(static_file(&root, "/missing.js").code(), Some(Code::NotFound)));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn mime_from_extension_falls_back_to_octet_stream() {
+        assert_eq!(mime_from_extension("wasm"), "application/wasm");
+        assert_eq!(mime_from_extension(".PNG"), "image/png");
+        assert_eq!(mime_from_extension("unknown"), "application/octet-stream");
+        assert_eq!(mime_from_extension(""), "application/octet-stream");
+    }
+
+    #[test]
+    fn malformed_versions_do_not_panic() {
+        for line in ["GET / HTTP/abc", "GET / HTTP/1", "GET / HTTP/x.y", "GET / HTTP/1.12"] {
+            let request = Message::parse(format!("{}\r\nHost: a\r\n\r\n", line).as_bytes());
+
+            assert!(matches!(request, Err(Error::Malformed(MalformedKind::Version))), "{}", line);
+        }
+    }
+
+    #[test]
+    fn request_line_has_three_tokens() {
+        let request = Message::parse(b"GET / HTTP/1.1 extra\r\nHost: a\r\n\r\n");
+
+        assert!(matches!(request, Err(Error::Malformed(MalformedKind::Version))));
+
+        let request = Message::parse(b"GET /\r\nHost: a\r\n\r\n");
+
+        assert!(matches!(request, Err(Error::Malformed(MalformedKind::StartLine))));
+    }
+
+    #[test]
+    fn unsupported_versions_get_505() {
+        let request = b"GET / HTTP/9.9\r\nHost: a\r\n\r\n";
+
+        assert!(matches!(Message::parse(request), Err(Error::UnsupportedVersion)));
+
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 2, echo);
+
+        for request in [&request[..], b"GET / HTTP/2.0\r\nHost: a\r\n\r\n"] {
+            let response = exchange(addr, request);
+
+            assert!(response.starts_with("HTTP/1.1 505 HTTP Version Not Supported\r\n"));
+        }
+
+        worker.join().unwrap();
+
+        let mut server = Server::bind(0).unwrap();
+
+        let addr = server.local_addr().unwrap();
+
+        let worker = thread::spawn(move || server.serve_streaming(|request, _| echo(request)));
+
+        assert!(exchange(addr, request).starts_with("HTTP/1.1 505 "));
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn binary_body_round_trips() {
+        let payload = vec![0x00, 0xff, 0xfe, 0x80, b'\r', b'\n'];
+
+        let response = MessageBuilder::new().body(payload.clone()).build();
+
+        assert_eq!(response.body(), payload);
+
+        let bytes = response.into_bytes();
+
+        let head = find_bytes(&bytes, b"\r\n\r\n").unwrap();
+
+        assert!(bytes[..head].ends_with(b"Content-Length: 6"));
+        assert_eq!(bytes[head + 4..], payload);
+    }
+
+    #[test]
+    fn client_closing_early_is_reported_not_fatal() {
+        let mut server = Server::bind(0).unwrap();
+
+        let errors = sync::Arc::new(sync::Mutex::new(vec![]));
+
+        let reported = errors.clone();
+
+        server.on_response(move |info| reported.lock().unwrap().push(info.error));
+
+        let (addr, worker) = spawn(server, 2, |request: &Message| match request.path() {
+            "/large" => MessageBuilder::new().body(vec![b'x'; 8 * 1024 * 1024]).build(),
+            _ => echo(request),
+        });
+
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+
+        stream.write_all(b"GET /large HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+
+        drop(stream);
+
+        let request = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nok";
+
+        assert!(exchange(addr, request).ends_with("\r\n\r\nok"));
+
+        worker.join().unwrap();
+
+        let errors = errors.lock().unwrap();
+
+        assert!(errors[0].is_some(), "{:?}", errors);
+        assert_eq!(errors[1], None);
+    }
+
+    #[test]
+    fn shutdown_stops_run() {
+        let mut server = Server::bind(0).unwrap();
+
+        let shutdown = server.shutdown_handle();
+
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            server.run(echo);
+
+            sender.send(()).unwrap();
+        });
+
+        thread::sleep(time::Duration::from_millis(50));
+
+        shutdown.shutdown();
+
+        receiver.recv_timeout(time::Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn bind_to_port_zero_reports_the_port() {
+        let server = Server::bind(0).unwrap();
+
+        let addr = server.local_addr().unwrap();
+
+        assert_ne!(addr.port(), 0);
+        assert!(net::TcpStream::connect(addr).is_ok());
+    }
+
+    #[test]
+    fn basic_auth_decodes_credentials() {
+        let credentials = Some(("user".to_string(), "pass".to_string()));
+
+        assert_eq!(get_with("Authorization", "Basic dXNlcjpwYXNz").basic_auth(), credentials);
+        assert_eq!(get_with("Authorization", "Basic dXNlcjpw!XNz").basic_auth(), None);
+        assert_eq!(get_with("Authorization", "Bearer dXNlcjpwYXNz").basic_auth(), None);
+    }
+
+    #[test]
+    fn unauthorized_quotes_the_realm() {
+        let response = Message::unauthorized("staff \"only\"").into_bytes();
+
+        let response = String::from_utf8(response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 401 Unauthorized\r\n"), "{}", response);
+        assert!(response.contains("\r\nWWW-Authenticate: Basic realm=\"staff \\\"only\\\"\"\r\n"));
+    }
+
+    #[test]
+    fn cookies_are_split_and_trimmed() {
+        let cookies = get_with("Cookie", "sid=abc; theme=dark").cookies();
+
+        assert_eq!(cookies, [("sid".into(), "abc".into()), ("theme".into(), "dark".into())]);
+
+        let cookies = get_with("Cookie", "quoted=\"a b\";;=x; flag").cookies();
+
+        assert_eq!(cookies, [("quoted".to_string(), "a b".to_string())]);
+    }
+
+    #[test]
+    fn cookie_serializes_attributes() {
+        let cookie = Cookie::new("sid", "abc")
+            .path("/")
+            .http_only()
+            .secure()
+            .max_age(3600)
+            .same_site(SameSite::Lax);
+
+        let expected = "sid=abc; Max-Age=3600; Path=/; Secure; HttpOnly; SameSite=Lax";
+
+        assert_eq!(cookie.to_string(), expected);
+
+        let header = Header::from(cookie);
+
+        assert_eq!(header.name, "Set-Cookie");
+    }
+
+    #[test]
+    fn range_requests_slice_the_body() {
+        let full = || MessageBuilder::new().body("0123456789").build();
+
+        let response = full().partial(&get_with("Range", "bytes=2-5"));
+
+        assert!(matches!(response.code(), Some(Code::PartialContent)));
+        assert_eq!(response.body(), b"2345");
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes 2-5/10"));
+        assert_eq!(response.headers().get("Content-Length"), Some("4"));
+
+        let response = full().partial(&get_with("Range", "bytes=7-"));
+
+        assert_eq!(response.body(), b"789");
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes 7-9/10"));
+
+        let response = full().partial(&get_with("Range", "bytes=20-"));
+
+        assert!(matches!(response.code(), Some(Code::RangeNotSatisfiable)));
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes */10"));
+    }
+
+    #[test]
+    fn redirect_sets_location() {
+        let response = Message::redirect(Code::MovedPermanently, "/new\r\nX-Injected: 1").unwrap();
+
+        assert!(matches!(response.code(), Some(Code::MovedPermanently)));
+        assert_eq!(response.headers().get("Location"), Some("/newX-Injected: 1"));
+
+        assert!(Message::redirect(Code::NotModified, "/new").is_none());
+        assert!(Message::redirect(Code::Success, "/new").is_none());
+    }
+
+    #[test]
+    fn oversized_bodies_get_413() {
+        let chunked = |size: usize| {
+            let head = "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n";
+
+            format!("{}{:X}\r\n{}\r\n0\r\n\r\n", head, size, "a".repeat(size))
+        };
+
+        let mut server = Server::bind(0).unwrap();
+
+        server.set_max_body_bytes(100);
+
+        let (addr, worker) = spawn(server, 2, echo);
+
+        let request = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 999999999\r\n\r\n";
+
+        assert!(exchange(addr, request).starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(exchange(addr, chunked(5000).as_bytes()).starts_with("HTTP/1.1 413 "));
+
+        worker.join().unwrap();
+
+        let mut server = Server::bind(0).unwrap();
+
+        server.set_max_body_bytes(100);
+
+        let addr = server.local_addr().unwrap();
+
+        let worker = thread::spawn(move || {
+            for _ in 0..2 {
+                server.serve_streaming(|_, body| {
+                    let mut read = vec![];
+
+                    let complete = body.read_to_end(&mut read).is_ok();
+
+                    assert!(read.len() <= 100);
+
+                    MessageBuilder::new().body(format!("{} {}", complete, read.len())).build()
+                });
+            }
+        });
+
+        let response = exchange(addr, chunked(5000).as_bytes());
+
+        assert!(response.starts_with("HTTP/1.1 413 "), "{}", response);
+
+        assert!(exchange(addr, chunked(60).as_bytes()).ends_with("\r\n\r\ntrue 60"));
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn obsolete_line_folding_is_refused() {
+        let request = b"GET / HTTP/1.1\r\nHost: a\r\nX-Long: first\r\n  second\r\n\r\n";
+
+        assert!(matches!(Message::parse(request), Err(Error::Malformed(MalformedKind::Header))));
+    }
+
+    #[test]
+    fn content_length_with_transfer_encoding_is_refused() {
+        let request = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\
+            Transfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n0\r\n\r\n";
+
+        assert!(matches!(Message::parse(request), Err(Error::Malformed(MalformedKind::Framing))));
+
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, echo);
+
+        assert!(exchange(addr, request).starts_with("HTTP/1.1 400 "));
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn content_length_must_be_digits_and_agree() {
+        let parse = |lengths: &[&str]| {
+            let mut request = "POST / HTTP/1.1\r\nHost: a\r\n".to_string();
+
+            for length in lengths {
+                request += &format!("Content-Length: {}\r\n", length);
+            }
+
+            Message::parse(format!("{}\r\nab", request).as_bytes())
+        };
+
+        assert_eq!(parse(&["2", "2"]).unwrap().body(), b"ab");
+
+        for lengths in [&["2", "1"][..], &["+2"], &["-0"], &["2 2"], &[""], &["0x2"]] {
+            let kind = match parse(lengths) {
+                Err(Error::Malformed(kind)) => Some(kind),
+                _ => None,
+            };
+
+            assert_eq!(kind, Some(MalformedKind::ContentLength), "{:?}", lengths);
+        }
+    }
+
+    #[test]
+    fn hooks_report_requests_and_responses() {
+        let mut server = Server::bind(0).unwrap();
+
+        let requests = sync::Arc::new(sync::Mutex::new(vec![]));
+        let responses = sync::Arc::new(sync::Mutex::new(vec![]));
+
+        let (seen, sent) = (requests.clone(), responses.clone());
+
+        server.on_request(move |info| seen.lock().unwrap().push(info.clone()));
+        server.on_response(move |info| sent.lock().unwrap().push(info.clone()));
+
+        let (addr, worker) = spawn(server, 1, echo);
+
+        let request = b"POST /log?x=1 HTTP/1.1\r\nHost: a\r\nContent-Length: 3\r\n\r\nabc";
+
+        let response = exchange(addr, request);
+
+        worker.join().unwrap();
+
+        let requests = requests.lock().unwrap();
+
+        assert_eq!(requests.len(), 1);
+        assert!(matches!(requests[0].method, Method::Post));
+        assert_eq!((requests[0].path.as_str(), requests[0].bytes), ("/log", 3));
+
+        let responses = responses.lock().unwrap();
+
+        assert_eq!(responses.len(), 1);
+        assert!(matches!(responses[0].method, Method::Post));
+        assert!(matches!(responses[0].code, Code::Success));
+        assert_eq!((responses[0].path.as_str(), responses[0].bytes), ("/log", response.len()));
+        assert_eq!(responses[0].error, None);
+    }
+
+    #[test]
+    fn elapsed_covers_the_handler() {
+        let mut server = Server::bind(0).unwrap();
+
+        let timings = sync::Arc::new(sync::Mutex::new(vec![]));
+
+        let timed = timings.clone();
+
+        server.on_response(move |info| timed.lock().unwrap().push(info.elapsed));
+
+        let (addr, worker) = spawn(server, 1, |request: &Message| {
+            thread::sleep(time::Duration::from_millis(150));
+
+            echo(request)
+        });
+
+        exchange(addr, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+
+        worker.join().unwrap();
+
+        let elapsed = timings.lock().unwrap()[0];
+
+        assert!(elapsed >= time::Duration::from_millis(150), "{:?}", elapsed);
+        assert!(elapsed < time::Duration::from_secs(2), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn methods_key_a_map() {
+        use Method::*;
+
+        let methods = [Get, Head, Post, Put, Delete, Connect, Options, Trace, Patch];
+        let extension = Extension("PURGE".into());
+
+        let mut map = std::collections::HashMap::new();
+
+        for (n, method) in methods.iter().chain([&extension]).enumerate() {
+            map.insert(method.clone(), n);
+        }
+
+        assert_eq!(map.len(), 10);
+
+        for (n, method) in methods.iter().chain([&extension]).enumerate() {
+            assert_eq!(map[method], n);
+        }
+
+        assert_eq!(map.get(&Extension("BREW".into())), None);
+    }
+
+    #[test]
+    fn methods_parse_from_str() {
+        assert_eq!("post".parse::<Method>().unwrap(), Method::Post);
+        assert_eq!("GET".parse::<Method>().unwrap(), Method::Get);
+
+        for invalid in ["", "GE T", "GET\r\n"] {
+            let kind = match invalid.parse::<Method>() {
+                Err(Error::Malformed(kind)) => Some(kind),
+                _ => None,
+            };
+
+            assert_eq!(kind, Some(MalformedKind::Method), "{:?}", invalid);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trips() {
+        let header = Header { name: "X-Trace".into(), value: "a, b".into() };
+
+        let json = serde_json::to_string(&header).unwrap();
+
+        assert_eq!(json, r#"{"name":"X-Trace","value":"a, b"}"#);
+
+        let header: Header = serde_json::from_str(&json).unwrap();
+
+        assert_eq!((header.name.as_str(), header.value.as_str()), ("X-Trace", "a, b"));
+
+        for method in [Method::Get, Method::Patch, Method::Extension("PURGE".into())] {
+            let json = serde_json::to_string(&method).unwrap();
+
+            assert_eq!(json, format!("\"{}\"", method));
+            assert_eq!(serde_json::from_str::<Method>(&json).unwrap(), method);
+        }
+
+        assert!(serde_json::from_str::<Method>("\"GE T\"").is_err());
+
+        let json = serde_json::to_string(&Version::HTTP_11).unwrap();
+
+        assert_eq!(serde_json::from_str::<Version>(&json).unwrap(), Version::HTTP_11);
+
+        assert_eq!(serde_json::to_string(&Code::NotFound).unwrap(), "404");
+        assert!(matches!(serde_json::from_str::<Code>("404").unwrap(), Code::NotFound));
+        assert!(serde_json::from_str::<Code>("999").is_err());
+    }
+
+    #[test]
+    fn iter_headers_keeps_order_and_duplicates() {
+        let request = b"GET / HTTP/1.1\r\nHost: a\r\nAccept: text/html\r\n\
+            X-Trace: 1\r\naccept: application/json\r\n\r\n";
+
+        let request = Message::parse(request).unwrap();
+
+        let headers = request
+            .iter_headers()
+            .map(|header| (header.name.as_str(), header.value.as_str()))
+            .collect::<Vec<_>>();
+
+        let expected = [
+            ("Host", "a"),
+            ("Accept", "text/html"),
+            ("X-Trace", "1"),
+            ("accept", "application/json"),
+        ];
+
+        assert_eq!(headers, expected);
+    }
+
+    #[test]
+    fn header_order_survives_a_round_trip() {
+        let request = b"POST /sign HTTP/1.1\r\n\
+            X-Amz-Date: 20260101T000000Z\r\n\
+            Host: a\r\n\
+            Accept: */*\r\n\
+            X-Amz-Date: again\r\n\
+            Content-Length: 2\r\n\
+            authorization: AWS4\r\n\
+            \r\n\
+            ok";
+
+        assert_eq!(Message::parse(request).unwrap().into_bytes(), request);
+    }
+
+    #[test]
+    fn header_casing_is_echoed_verbatim() {
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, |request: &Message| {
+            let sent = request.iter_headers().find(|h| h.name.eq_ignore_ascii_case("x-weird-case"));
+
+            let header = sent.map(|h| Header { name: h.name.clone(), value: h.value.clone() });
+
+            MessageBuilder::new().header(header.unwrap()).build()
+        });
+
+        let response = exchange(addr, b"GET / HTTP/1.1\r\nHost: a\r\nx-WeIrD-cAsE: 1\r\n\r\n");
+
+        worker.join().unwrap();
+
+        assert!(response.contains("\r\nx-WeIrD-cAsE: 1\r\n"), "{}", response);
+
+        let request = get_with("x-WeIrD-cAsE", "1");
+
+        assert_eq!(request.headers().get("X-WEIRD-CASE"), Some("1"));
+    }
+
+    #[test]
+    fn continue_is_sent_before_the_body_is_read() {
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, echo);
+
+        let mut stream = net::TcpStream::connect(addr).unwrap();
 
-        const version: &'static str = "0.1";
+        stream.set_read_timeout(Some(time::Duration::from_secs(10))).unwrap();
 
-        let mut message = MessageBuilder::new();
+        let head = b"PUT / HTTP/1.1\r\nHost: a\r\n\
+            Expect: 100-continue\r\nContent-Length: 2\r\n\r\n";
 
-        headers! { message, 
-            "Server": format!("{}/{}", "Persona", version),
-            "Content-type": "text/html", 
-            "Content-Length": body.bytes().len()
+        stream.write_all(head).unwrap();
+
+        let interim = b"HTTP/1.1 100 Continue\r\n\r\n";
+
+        let mut buffer = vec![0; interim.len()];
+
+        stream.read_exact(&mut buffer).unwrap();
+
+        assert_eq!(buffer, interim);
+
+        stream.write_all(b"ok").unwrap();
+
+        stream.shutdown(net::Shutdown::Write).unwrap();
+
+        let mut response = String::new();
+
+        stream.read_to_string(&mut response).unwrap();
+
+        worker.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 "), "{}", response);
+        assert!(response.ends_with("\r\n\r\nok"), "{}", response);
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_in_order() {
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, |request: &Message| {
+            MessageBuilder::new().body(request.path()).build()
+        });
+
+        let requests = b"GET /first HTTP/1.1\r\nHost: a\r\n\r\n\
+            GET /second HTTP/1.1\r\nHost: a\r\n\r\n";
+
+        let response = exchange(addr, requests);
+
+        worker.join().unwrap();
+
+        let first = response.find("\r\n\r\n/first").unwrap();
+        let second = response.find("\r\n\r\n/second").unwrap();
+
+        assert!(first < second, "{}", response);
+        assert_eq!(response.matches("HTTP/1.1 200 ").count(), 2, "{}", response);
+    }
+
+    #[test]
+    fn parse_with_len_reports_the_bytes_consumed() {
+        let request = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello";
+
+        let mut buffer = request.to_vec();
+
+        buffer.extend_from_slice(b"GET /next HTTP/1.1\r\n");
+
+        let (message, consumed) = Message::parse_with_len(&buffer).unwrap();
+
+        assert_eq!(consumed, request.len());
+        assert_eq!(message.body(), b"hello");
+
+        let chunked = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+            2\r\nok\r\n0\r\n\r\n";
+
+        assert_eq!(Message::parse_with_len(chunked).unwrap().1, chunked.len());
+    }
+
+    #[test]
+    fn incremental_parse_tells_partial_from_invalid() {
+        let state = |buffer: &[u8]| match Message::parse_incremental(buffer, 1024) {
+            ParseState::Complete(_, length) => Ok(Some(length)),
+            ParseState::Partial => Ok(None),
+            ParseState::Error(Error::Malformed(kind)) => Err(Some(kind)),
+            ParseState::Error(_) => Err(None),
+        };
+
+        let sized = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello";
+
+        assert_eq!(state(&sized[..20]), Ok(None));
+        assert_eq!(state(&sized[..sized.len() - 2]), Ok(None));
+        assert_eq!(state(sized), Ok(Some(sized.len())));
+
+        let chunked = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+            5\r\nhello\r\n0\r\n\r\n";
+
+        for end in chunked.len() - 18..chunked.len() {
+            assert_eq!(state(&chunked[..end]), Ok(None), "{}", end);
+        }
+
+        assert_eq!(state(chunked), Ok(Some(chunked.len())));
+
+        let malformed = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+            zz\r\nhello\r\n0\r\n\r\n";
+
+        assert_eq!(state(malformed), Err(Some(MalformedKind::Chunk)));
+
+        let header = b"GET / HTTP/1.1\r\nHost: a\r\nbad\r\n\r\n";
+
+        assert_eq!(state(header), Err(Some(MalformedKind::Header)));
+
+        assert_eq!(state(&[b'a'; 2048]), Err(Some(MalformedKind::HeaderTooLarge)));
+
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, echo);
+
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+
+        stream.set_read_timeout(Some(time::Duration::from_secs(10))).unwrap();
+
+        stream.write_all(malformed).unwrap();
+
+        let mut response = String::new();
+
+        stream.read_to_string(&mut response).unwrap();
+
+        worker.join().unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 400 "), "{}", response);
+    }
+
+    #[test]
+    fn large_requests_take_few_reads() {
+        let size = 1024 * 1024;
+
+        let head = format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n", size);
+
+        let request = [head.into_bytes(), vec![b'x'; size]].concat();
+
+        let length = request.len();
+
+        let mut connection = Connection::new(Recorded::new(request));
+
+        assert_eq!(connection.read_request().unwrap().body().len(), size);
+
+        // Reading 8 bytes at a time would take over 130,000 reads for this request.
+        let reads = connection.get_ref().reads;
+
+        assert!(reads <= length / READ_BUFFER_BYTES + 1, "{} reads", reads);
+    }
+
+    #[test]
+    fn request_ref_borrows_from_the_buffer() {
+        let buffer = b"PUT /items/7 HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\n\r\nbody".to_vec();
+
+        let within = |slice: &[u8]| buffer.as_ptr_range().contains(&slice.as_ptr());
+
+        let request = Message::parse_ref(&buffer).unwrap();
+
+        assert_eq!((request.method(), request.target()), ("PUT", "/items/7"));
+        assert!(within(request.method().as_bytes()) && within(request.target().as_bytes()));
+
+        for (name, value) in request.headers() {
+            assert!(within(name.as_bytes()) && within(value.as_bytes()), "{}", name);
+        }
+
+        assert_eq!(request.body(), b"body");
+        assert_eq!(request.body().as_ptr(), buffer[buffer.len() - 4..].as_ptr());
+
+        let owned = request.to_owned();
+
+        assert_eq!((owned.path(), owned.body()), ("/items/7", &b"body"[..]));
+    }
+
+    #[test]
+    fn parsers_agree_on_where_a_message_ends() {
+        let chunked = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n\
+            4\r\nbody\r\n0\r\n\r\n";
+
+        assert_eq!(Message::expected_len(chunked).unwrap(), Some(chunked.len()));
+        assert_eq!(Message::parse_with_len(chunked).unwrap().1, chunked.len());
+        assert_eq!(Message::parse_ref(chunked).unwrap().body(), b"body");
+
+        // A coding that only mentions chunked leaves the body unframed.
+        let named = b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: xchunked\r\n\r\n4\r\nbody";
+
+        let head = named.len() - 7;
+
+        assert_eq!(Message::expected_len(named).unwrap(), Some(head));
+        assert_eq!(Message::parse_with_len(named).unwrap().1, head);
+        assert_eq!(Message::parse_ref(named).unwrap().body(), b"");
+    }
+
+    #[test]
+    fn method_override_reroutes_posts() {
+        let reply = |body: &'static str| {
+            move |_: &Message, _: &Params| MessageBuilder::new().body(body).build()
+        };
+
+        let router = || {
+            let router = Router::new()
+                .route(Method::Get, "/item", reply("got"))
+                .route(Method::Post, "/item", reply("created"))
+                .route(Method::Delete, "/item", reply("deleted"));
+
+            move |request: &Message| router.handle(request)
+        };
+
+        let tunnelled = |method: &str| {
+            let head = format!("{} /item HTTP/1.1\r\nHost: a\r\n", method);
+
+            head + "X-HTTP-Method-Override: DELETE\r\n\r\n"
+        };
+
+        let mut server = Server::bind(0).unwrap();
+
+        server.allow_method_override(true);
+
+        let (addr, worker) = spawn(server, 2, router());
+
+        assert!(exchange(addr, tunnelled("POST").as_bytes()).ends_with("\r\n\r\ndeleted"));
+        assert!(exchange(addr, tunnelled("GET").as_bytes()).ends_with("\r\n\r\ngot"));
+
+        worker.join().unwrap();
+
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, router());
+
+        assert!(exchange(addr, tunnelled("POST").as_bytes()).ends_with("\r\n\r\ncreated"));
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn cors_allows_listed_origins_only() {
+        let mut server = Server::bind(0).unwrap();
+
+        let cors = Cors::new().origin("https://app.example").method(Method::Get);
+
+        server.set_cors(cors.credentials(true));
+
+        let (addr, worker) = spawn(server, 4, echo);
+
+        let from = |origin: &str, method: &str| {
+            let request = format!("{} / HTTP/1.1\r\nHost: a\r\nOrigin: {}\r\n", method, origin);
+
+            exchange(addr, (request + "Access-Control-Request-Method: GET\r\n\r\n").as_bytes())
+        };
+
+        let allowed = from("https://app.example", "GET");
+
+        assert!(allowed.contains("\r\nAccess-Control-Allow-Origin: https://app.example\r\n"));
+        assert!(allowed.contains("\r\nAccess-Control-Allow-Credentials: true\r\n"));
+        assert!(allowed.contains("\r\nVary: Origin\r\n"));
+
+        let refused = from("https://evil.example", "GET");
+
+        assert!(refused.starts_with("HTTP/1.1 200 "), "{}", refused);
+        assert!(!refused.contains("Access-Control-Allow-"), "{}", refused);
+
+        let preflight = from("https://app.example", "OPTIONS");
+
+        assert!(preflight.starts_with("HTTP/1.1 204 "), "{}", preflight);
+        assert!(preflight.contains("\r\nAccess-Control-Allow-Methods: GET\r\n"), "{}", preflight);
+
+        let preflight = from("https://evil.example", "OPTIONS");
+
+        assert!(!preflight.contains("Access-Control-Allow-"), "{}", preflight);
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn cors_wildcard_never_sends_credentials() {
+        let mut server = Server::bind(0).unwrap();
+
+        server.set_cors(Cors::new().origin("*").credentials(true));
+
+        let (addr, worker) = spawn(server, 1, echo);
+
+        let request = b"GET / HTTP/1.1\r\nHost: a\r\nOrigin: https://any.example\r\n\r\n";
+
+        let response = exchange(addr, request);
+
+        worker.join().unwrap();
+
+        assert!(response.contains("\r\nAccess-Control-Allow-Origin: *\r\n"), "{}", response);
+        assert!(!response.contains("any.example"), "{}", response);
+        assert!(!response.contains("Access-Control-Allow-Credentials"), "{}", response);
+    }
+
+    #[test]
+    fn middleware_layers_wrap_the_handler() {
+        let layer = |name: &'static str| {
+            move |request: &Message, next: &dyn Fn(&Message) -> Message| {
+                let mut response = next(request);
+
+                response.push_header(Header { name: name.into(), value: "1".into() });
+
+                response
+            }
+        };
+
+        let mut server = Server::bind(0).unwrap();
+
+        server.wrap(layer("X-Outer"));
+        server.wrap(layer("X-Inner"));
+
+        let handler = |_: &Message| MessageBuilder::new().body("handled").build();
+
+        let (addr, worker) = spawn(server, 1, handler);
+
+        let response = exchange(addr, b"GET / HTTP/1.1\r\nHost: a\r\n\r\n");
+
+        worker.join().unwrap();
+
+        let inner = response.find("\r\nX-Inner: 1\r\n");
+        let outer = response.find("\r\nX-Outer: 1\r\n");
+
+        assert!(inner.is_some() && inner < outer, "{}", response);
+        assert!(response.ends_with("\r\n\r\nhandled"), "{}", response);
+    }
+
+    #[test]
+    fn streamed_bodies_are_written_as_they_are_read() {
+        struct Tracked(io::Take<io::Repeat>, sync::Arc<atomic::AtomicUsize>);
+
+        impl io::Read for Tracked {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let read = self.0.read(buf)?;
+
+                self.1.fetch_add(read, atomic::Ordering::SeqCst);
+
+                Ok(read)
+            }
+        }
+
+        struct Watched(Vec<u8>, sync::Arc<atomic::AtomicUsize>, Option<usize>);
+
+        impl io::Write for Watched {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                let written = self.0.write(buf)?;
+
+                if self.0.len() >= 1024 * 1024 {
+                    self.2.get_or_insert(self.1.load(atomic::Ordering::SeqCst));
+                }
+
+                Ok(written)
+            }
+
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let size = 10 * 1024 * 1024;
+
+        let read = sync::Arc::new(atomic::AtomicUsize::new(0));
+
+        let reader = Tracked(io::repeat(b'x').take(size as u64), read.clone());
+
+        let mut writer = Watched(vec![], read, None);
+
+        MessageBuilder::new().body_stream(reader).build().write_to(&mut writer).unwrap();
+
+        // Buffering the body would read all of it before the first mebibyte was written.
+        assert!(writer.2.unwrap() < size / 2, "{:?}", writer.2);
+
+        let response = Message::parse(&writer.0).unwrap();
+
+        assert_eq!(response.headers().get("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(response.body().len(), size);
+    }
+
+    #[test]
+    fn stalled_body_after_continue_is_dropped() {
+        let mut server = Server::bind(0).unwrap();
+
+        server.set_continue_timeout(time::Duration::from_millis(200));
+
+        let (addr, worker) = spawn(server, 1, echo);
+
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+
+        stream.set_read_timeout(Some(time::Duration::from_secs(10))).unwrap();
+
+        let head = b"PUT / HTTP/1.1\r\nHost: a\r\n\
+            Expect: 100-continue\r\nContent-Length: 2\r\n\r\n";
+
+        stream.write_all(head).unwrap();
+
+        let started = time::Instant::now();
+
+        let mut response = String::new();
+
+        stream.read_to_string(&mut response).ok();
+
+        worker.join().unwrap();
+
+        assert_eq!(response, "HTTP/1.1 100 Continue\r\n\r\n");
+        assert!(started.elapsed() >= time::Duration::from_millis(200));
+        assert!(started.elapsed() < time::Duration::from_secs(5));
+    }
+
+    #[test]
+    fn trailers_round_trip() {
+        let response = MessageBuilder::new()
+            .body("signed")
+            .trailer("Content-Signature", "abc123")
+            .build();
+
+        let bytes = response.into_bytes();
+
+        let text = String::from_utf8_lossy(&bytes).into_owned();
+
+        assert!(text.contains("\r\nTransfer-Encoding: chunked\r\n"), "{}", text);
+        assert!(text.contains("\r\nTrailer: Content-Signature\r\n"), "{}", text);
+        assert!(text.ends_with("\r\n0\r\nContent-Signature: abc123\r\n\r\n"), "{}", text);
+
+        let parsed = Message::parse(&bytes).unwrap();
+
+        assert_eq!(parsed.body(), b"signed");
+        assert_eq!(parsed.trailers().and_then(|t| t.get("content-signature")), Some("abc123"));
+        assert_eq!(parsed.headers().get("Content-Signature"), None);
+    }
+
+    #[test]
+    fn malformed_kinds_name_the_failure() {
+        use MalformedKind::*;
+
+        let framed = b"POST / HTTP/1.1\r\nHost: a\r\n\
+            Transfer-Encoding: chunked\r\nContent-Length: 2\r\n\r\nok";
+
+        let cases: [(&[u8], MalformedKind); 13] = [
+            (b"GET / HTTP/1.1\r\nHost: a\r\n", Incomplete),
+            (b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhe", Incomplete),
+            (b"GET /\r\nHost: a\r\n\r\n", StartLine),
+            (b"G(T / HTTP/1.1\r\nHost: a\r\n\r\n", Method),
+            (b"GET /a\x01 HTTP/1.1\r\nHost: a\r\n\r\n", Target),
+            (b"GET / HTTP/1.x\r\nHost: a\r\n\r\n", Version),
+            (b"HTTP/1.1 999 Nope\r\n\r\n", Status),
+            (b"GET / HTTP/1.1\r\nHost: a\r\nno colon\r\n\r\n", Header),
+            (b"GET / HTTP/1.1\r\nHost: a\r\nX: a\x01b\r\n\r\n", Value),
+            (b"GET / HTTP/1.1\r\n\r\n", Host),
+            (b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: x\r\n\r\n", ContentLength),
+            (b"POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n", Chunk),
+            (framed, Framing),
+        ];
+
+        for (message, expected) in cases {
+            let kind = match Message::parse(message) {
+                Err(Error::Malformed(kind)) => Some(kind),
+                _ => None,
+            };
+
+            assert_eq!(kind, Some(expected), "{}", String::from_utf8_lossy(message));
+        }
+
+        let unsupported = Message::parse(b"GET / HTTP/3.7\r\nHost: a\r\n\r\n");
+
+        assert!(matches!(unsupported, Err(Error::UnsupportedVersion)));
+
+        let error = Error::Malformed(Target);
+
+        assert_eq!(error.to_string(), "Malformed: invalid request target");
+    }
+
+    #[test]
+    fn io_errors_keep_their_source() {
+        let io_error = Error::from(io::Error::new(io::ErrorKind::TimedOut, "slow client"));
+
+        let source = error::Error::source(&io_error).map(ToString::to_string);
+
+        assert_eq!(source.as_deref(), Some("slow client"));
+
+        let server = Server::bind(0).unwrap();
+
+        let taken = Server::bind(server.local_addr().unwrap().port()).err().unwrap();
+
+        assert!(matches!(taken, Error::AddrInUse(..)));
+        assert!(error::Error::source(&taken).is_some());
+
+        assert!(error::Error::source(&Error::Malformed(MalformedKind::Chunk)).is_none());
+    }
+
+    #[test]
+    fn addr_in_use_names_the_address() {
+        let server = Server::bind(0).unwrap();
+
+        let port = server.local_addr().unwrap().port();
+
+        let message = Server::bind(port).err().unwrap().to_string();
+
+        assert_eq!(message, format!("address already in use: 127.0.0.1:{}", port));
+    }
+
+    #[test]
+    fn bind_all_serves_every_listener() {
+        let any: net::SocketAddr = "127.0.0.1:0".parse().unwrap();
+
+        let server = Server::bind_all(&[any, any]).unwrap();
+
+        let addrs = server.local_addrs().to_vec();
+
+        assert_eq!(addrs.len(), 2);
+        assert_ne!(addrs[0].port(), addrs[1].port());
+
+        let (_, worker) = spawn(server, 2, echo);
+
+        for addr in addrs.iter().rev() {
+            let request = b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\n\r\nboth";
+
+            assert!(exchange(*addr, request).ends_with("\r\n\r\nboth"), "{}", addr);
+        }
+
+        worker.join().unwrap();
+
+        assert!(Server::bind_all(&[]).is_err());
+    }
+
+    #[test]
+    fn strict_mode_refuses_get_bodies() {
+        let with_body = b"GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nhi";
+
+        let mut server = Server::bind(0).unwrap();
+
+        server.set_strict(true);
+
+        let (addr, worker) = spawn(server, 2, echo);
+
+        assert!(exchange(addr, with_body).starts_with("HTTP/1.1 400 "));
+
+        let empty = b"GET / HTTP/1.1\r\nHost: a\r\nContent-Length: 0\r\n\r\n";
+
+        assert!(exchange(addr, empty).starts_with("HTTP/1.1 200 "));
+
+        worker.join().unwrap();
+
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, echo);
+
+        assert!(exchange(addr, with_body).starts_with("HTTP/1.1 200 "));
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn auto_options_lists_routes_with_cors() {
+        let custom = |_: &Message, _: &Params| MessageBuilder::new().body("custom").build();
+
+        let router = Router::new()
+            .get("/items", ok)
+            .route(Method::Delete, "/items", ok)
+            .route(Method::Options, "/custom", custom);
+
+        let mut server = Server::bind(0).unwrap();
+
+        server.set_cors(Cors::new().origin("https://app.example"));
+
+        let (addr, worker) = spawn(server, 2, move |request: &Message| router.handle(request));
+
+        let options = |path: &str| {
+            let request = format!("OPTIONS {} HTTP/1.1\r\nHost: a\r\n", path);
+
+            exchange(addr, (request + "Origin: https://app.example\r\n\r\n").as_bytes())
         };
 
-        body! { message, body };
+        let response = options("/items");
+
+        assert!(response.starts_with("HTTP/1.1 204 "), "{}", response);
+        assert!(response.contains("\r\nAllow: GET, DELETE, HEAD, OPTIONS\r\n"), "{}", response);
+        assert!(response.contains("\r\nAccess-Control-Allow-Origin: https://app.example\r\n"));
+
+        assert!(options("/custom").ends_with("\r\n\r\ncustom"));
+
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn trace_echoes_the_request() {
+        let mut server = Server::bind(0).unwrap();
+
+        server.allow_trace(true);
+
+        let (addr, worker) = spawn(server, 1, echo);
+
+        let reflected = "TRACE /debug?x=1 HTTP/1.1\r\nHost: a\r\nX-Probe: 1\r\n\r\n";
+
+        let request = "TRACE /debug?x=1 HTTP/1.1\r\nHost: a\r\n\
+            Cookie: secret\r\nX-Probe: 1\r\n\r\n";
+
+        let response = exchange(addr, request.as_bytes());
+
+        worker.join().unwrap();
+
+        assert!(response.contains("\r\nContent-Type: message/http\r\n"), "{}", response);
+        assert!(response.ends_with(&format!("\r\n\r\n{}", reflected)), "{}", response);
+        assert!(!response.contains("secret"), "{}", response);
+
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, echo);
+
+        let response = exchange(addr, request.as_bytes());
+
+        worker.join().unwrap();
+
+        assert!(!response.contains("message/http"), "{}", response);
+    }
+
+    #[test]
+    fn server_header_is_configurable() {
+        let request = b"GET / HTTP/1.1\r\nHost: a\r\n\r\n";
+
+        let (addr, worker) = spawn(Server::bind(0).unwrap(), 1, echo);
+
+        let default = format!("\r\nServer: http/{}\r\n", env!("CARGO_PKG_VERSION"));
+
+        assert!(exchange(addr, request).contains(&default));
+
+        worker.join().unwrap();
+
+        let mut server = Server::bind(0).unwrap();
+
+        server.server_name("edge/2");
+
+        let (addr, worker) = spawn(server, 1, echo);
+
+        assert!(exchange(addr, request).contains("\r\nServer: edge/2\r\n"));
+
+        worker.join().unwrap();
+
+        let mut server = Server::bind(0).unwrap();
+
+        server.hide_server_name();
 
-        let response = message.build().into_bytes();
+        let (addr, worker) = spawn(server, 1, echo);
 
-        stream.write(&response).expect("failed to write to stream");
+        assert!(!exchange(addr, request).contains("\r\nServer:"));
 
-        stream.shutdown(net::Shutdown::Write).expect("failed to shutdown stream");
+        worker.join().unwrap();
     }
 }
@@ -0,0 +1,134 @@
+//! Counts the heap allocations parsing and serving make, in a binary of its own so the
+//! counting allocator does not sit under every other test.
+
+use std::alloc;
+use std::cell;
+use std::io;
+use std::net;
+use std::thread;
+
+use std::io::{Read, Write};
+
+use http::{Connection, Message, MessageBuilder, Server};
+
+/// Counts heap allocations per thread, so tests can measure the ones they make.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: cell::Cell<usize> = const { cell::Cell::new(0) };
+}
+
+unsafe impl alloc::GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: alloc::Layout) -> *mut u8 {
+        ALLOCATIONS.try_with(|count| count.set(count.get() + 1)).ok();
+
+        alloc::System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: alloc::Layout) {
+        alloc::System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: alloc::Layout, size: usize) -> *mut u8 {
+        ALLOCATIONS.try_with(|count| count.set(count.get() + 1)).ok();
+
+        alloc::System.realloc(ptr, layout, size)
+    }
+}
+
+#[global_allocator]
+static COUNTING: Counting = Counting;
+
+/// Runs `f`, returning its result with the number of allocations it made on this thread.
+fn allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(cell::Cell::get);
+
+    let result = f();
+
+    (result, ALLOCATIONS.with(cell::Cell::get) - before)
+}
+
+#[test]
+fn parse_allocations_do_not_grow_with_the_request() {
+    let request = |size: usize| {
+        let head = format!(
+            "POST /upload HTTP/1.1\r\nHost: a\r\nX-Long: {}\r\nContent-Length: {}\r\n\r\n",
+            "v".repeat(size),
+            size
+        );
+
+        [head.into_bytes(), vec![b'x'; size]].concat()
+    };
+
+    let (small, large) = (request(16), request(1024 * 1024));
+
+    let (parsed, few) = allocations(|| Message::parse(&small).map(drop));
+
+    parsed.unwrap();
+
+    let (parsed, many) = allocations(|| Message::parse(&large).map(drop));
+
+    parsed.unwrap();
+
+    // Slicing the head and body costs the same however long they are, collecting them
+    // character by character would not.
+    assert!(few > 0);
+    assert_eq!(few, many);
+}
+
+#[test]
+fn known_lengths_are_reserved_up_front() {
+    let read = |size: usize| {
+        let head = format!("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: {}\r\n\r\n", size);
+
+        let request = [head.into_bytes(), vec![1; size]].concat();
+
+        let mut connection = Connection::new(io::Cursor::new(request));
+
+        let (request, count) = allocations(|| connection.read_request().map(drop));
+
+        request.unwrap();
+
+        count
+    };
+
+    // Growing the buffer as reads arrive would reallocate more often for the larger request.
+    assert_eq!(read(64 * 1024), read(1024 * 1024));
+}
+
+#[test]
+fn short_connections_reuse_read_buffers() {
+    let mut server = Server::bind(0).unwrap();
+
+    let addr = server.local_addr().unwrap();
+
+    let connections = 32;
+
+    let worker = thread::spawn(move || {
+        let respond = |_: &Message| MessageBuilder::new().body("ok").build();
+
+        (0..=connections).map(|_| allocations(|| server.serve(respond)).1).collect::<Vec<_>>()
+    });
+
+    let request = b"POST / HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\
+        Content-Length: 5\r\n\r\nhello";
+
+    let mut response = vec![];
+
+    for _ in 0..=connections {
+        let mut stream = net::TcpStream::connect(addr).unwrap();
+
+        stream.write_all(request).unwrap();
+
+        response.clear();
+
+        stream.read_to_end(&mut response).unwrap();
+    }
+
+    let counts = worker.join().unwrap();
+
+    // The first connection finds the pool empty and allocates its buffer; every later one takes
+    // that buffer back, so none of them allocates one and their counts stay flat.
+    assert!(counts[1..].iter().all(|&count| count == counts[1]), "{:?}", counts);
+    assert!(counts[1] < counts[0], "{:?}", counts);
+}